@@ -1,4 +1,4 @@
-use common::{clone, TokenPair};
+use common::{clone, BoardUrl, TokenPair};
 use gloo_console::log;
 use yew::prelude::*;
 use yew_router::prelude::*;
@@ -6,9 +6,10 @@ use yew_router::prelude::*;
 use super::about::About;
 use super::not_found::{NotFound, NotFoundTyp};
 use crate::auth::{Auth, AuthRoutes};
-use crate::board::BoardView;
+use crate::board::{BoardView, SlugView};
 use crate::layout::{DefaultBoard, LayoutView};
 use crate::rtr_client::use_token_pair;
+use crate::share::ShareView;
 
 #[derive(Debug, Clone, Routable, PartialEq)]
 pub enum Routes {
@@ -16,6 +17,10 @@ pub enum Routes {
   Home,
   #[at("/board/:id")]
   Board { id: String },
+  #[at("/b/:user/:slug")]
+  Slug { user: String, slug: String },
+  #[at("/share/:token")]
+  Share { token: String },
   #[at("/foo/123/:id")]
   FooBarBaz{ id: i64 },
   #[at("/about")]
@@ -29,6 +34,18 @@ pub enum Routes {
   Auth,
 }
 
+/// A board on its own, or in the layout of the user if they are logged in
+pub fn board_page(id: BoardUrl, tokens: Option<&TokenPair>) -> Html {
+  match tokens {
+    None => html!{ <main><BoardView {id} /></main> },
+    Some(tokens@TokenPair{ access_token: access, .. }) => html!{
+      <ContextProvider<TokenPair> context={tokens.clone()}>
+        <LayoutView access_token={access.clone()} board_id={id} />
+      </ContextProvider<TokenPair>>
+    },
+  }
+}
+
 #[function_component(App)]
 pub fn app() -> Html {
   let token_pair = use_token_pair();
@@ -40,14 +57,12 @@ pub fn app() -> Html {
           (Routes::NotFound, _) => html!{ <NotFound typ={NotFoundTyp::Route} /> },
           (Routes::FooBarBaz{ id }, _) => html!{ <p>{"FooBarBaz"}{id}</p> },
           (Routes::About, _) => html!{ <About /> },
-          (Routes::Board{ id }, None) => html!{
-            <main><BoardView id={id.parse::<i64>().unwrap()} /></main>
-          },
-          (Routes::Board{ id }, Some(tokens@TokenPair{ access_token: access, .. })) => html!{
-            <ContextProvider<TokenPair> context={tokens.clone()}>
-              <LayoutView access_token={access.clone()} board_id={id.parse::<i64>().unwrap()} />
-            </ContextProvider<TokenPair>>
+          (Routes::Share{ token }, _) => html!{ <ShareView {token} /> },
+          (Routes::Board{ id }, _) if !BoardUrl(id.clone()).is_well_formed() => html!{
+            <NotFound typ={NotFoundTyp::Board} />
           },
+          (Routes::Board{ id }, tokens) => board_page(BoardUrl(id), tokens.as_ref()),
+          (Routes::Slug{ user, slug }, _) => html!{ <SlugView {user} {slug} /> },
           (Routes::Home, None) => html!{ <Redirect<Routes> to={Routes::About} /> },
           (Routes::Home, Some(tokens)) => html!{
             <DefaultBoard access_token={tokens.access_token.clone()} />
@@ -1,107 +1,352 @@
 use crate::not_found::NotFound;
 use std::future::ready;
 
-use common::BoardDetails;
-use gloo_net::http::Request;
+use common::layout::{BoardLayout, BoardLayoutV2, Entry, Item, ItemKind, SEARCH_PLACEHOLDER};
+use common::versioned::Versioned;
+use common::{
+  clone, BatchEntry, BatchRequest, BatchResponse, BatchResult, BoardDetails, BoardUrl, TokenPair,
+};
+use gloo_console::log;
+use gloo_net::http::{Request, RequestBuilder};
 use serde::{Deserialize, Serialize};
-use yew::{function_component, hook, html, use_effect_with, use_state, Html, Properties};
+use web_sys::js_sys::encode_uri_component;
+use web_sys::HtmlInputElement;
+use yew::{
+  function_component, hook, html, use_context, use_effect_with, use_node_ref, use_state, Html,
+  Properties, SubmitEvent,
+};
+use yew_router::prelude::{use_navigator, Link};
 
 use crate::api;
+use crate::app::{board_page, Routes};
 use crate::not_found::NotFoundTyp;
-use crate::util::{retry, use_local_storage_unf};
+use crate::share::grant_key;
+use crate::util::{retry, set_local_storage, use_local_storage_unf};
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
-pub enum BoardLayout {
-  V1(BoardLayoutV1),
+#[derive(Debug, Clone, Copy, Deserialize, Hash, PartialEq, Eq, Serialize)]
+pub struct BoardNotFound {
+  /// The board is in its owner's trash rather than gone for good
+  pub trashed: bool,
 }
-impl Default for BoardLayout {
-  fn default() -> Self {
-      Self::V1(BoardLayoutV1{ sections: Vec::new() })
+
+fn layout_key(board: &str) -> String { format!("tagged layout of board {board}") }
+pub fn meta_key(board: &str) -> String { format!("meatdata of board {board}") }
+
+/// Local storage key of the boards found to have moved, from old to new url
+pub const MOVED_BOARDS: &str = "moved boards";
+
+/// Carry everything cached about a board over to its new url, and remember the
+/// move so that the user's layout can follow it
+fn board_moved(from: &str, to: &str) {
+  use std::collections::HashMap;
+
+  use gloo_storage::{LocalStorage, Storage as _};
+
+  for key in [layout_key as fn(&str) -> String, meta_key, grant_key] {
+    if let Ok(val) = LocalStorage::get::<serde_json::Value>(key(from)) {
+      LocalStorage::set(key(to), val).unwrap();
+      LocalStorage::delete(key(from));
+    }
   }
+  let mut moved = LocalStorage::get::<HashMap<String, String>>(MOVED_BOARDS).unwrap_or_default();
+  moved.insert(from.to_string(), to.to_string());
+  LocalStorage::set(MOVED_BOARDS, moved).unwrap();
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Hash, PartialEq, Eq, Serialize)]
-pub struct BoardNotFound;
+/// Bring the cached copies of many boards up to date in one request, so that
+/// they show up at once when opened. Boards opened through a share link are
+/// left to [use_board_layout], since the grant only applies to its own board.
+pub async fn prefetch_boards(boards: Vec<BoardUrl>, tokens: Option<TokenPair>) {
+  use gloo_storage::{LocalStorage, Storage as _};
+
+  let boards = (boards.into_iter())
+    .filter(|url| LocalStorage::get::<String>(grant_key(&url.0)).is_err())
+    .map(|url| {
+      let version = LocalStorage::get::<BoardDetails>(meta_key(&url.0)).ok().map(|d| d.version);
+      BatchEntry { url, version }
+    })
+    .collect::<Vec<_>>();
+  if boards.is_empty() {
+    return;
+  }
+  let mut req = Request::post(&api("boards/batch"));
+  if let Some(tokens) = &tokens {
+    req = req.header("Authorization", &format!("Bearer {}", tokens.access_token));
+  }
+  // the boards are still loaded one by one when opened if this fails
+  let rep = match req.json(&BatchRequest { boards }).unwrap().send().await {
+    Ok(rep) if rep.ok() => rep,
+    Ok(rep) => {
+      log!(format!("Failed to prefetch boards: {}", rep.status()));
+      return;
+    },
+    Err(e) => {
+      log!(format!("Failed to prefetch boards: {e}"));
+      return;
+    },
+  };
+  for result in rep.json::<BatchResponse>().await.unwrap().boards {
+    match result {
+      BatchResult::Changed { details, layout } => {
+        // the same ETag a single read would have answered with
+        let etag = format!("\"{}\"", details.version);
+        set_local_storage(&layout_key(&details.url.0), (Some(etag), layout.upgrade()));
+        set_local_storage(&meta_key(&details.url.0), details);
+      },
+      BatchResult::Moved { from, to } => board_moved(&from.0, &to.0),
+      BatchResult::Gone { url, .. } => {
+        LocalStorage::delete(layout_key(&url.0));
+        LocalStorage::delete(meta_key(&url.0));
+      },
+      BatchResult::Unreadable { url } => log!(format!("Couldn't prefetch board {url}")),
+    }
+  }
+}
 
 #[hook]
-pub fn use_board_layout(id: i64) -> Option<Result<BoardLayout, BoardNotFound>> {
+pub fn use_board_layout(id: BoardUrl) -> Option<Result<BoardLayout, BoardNotFound>> {
   use std::time::Duration;
 
-  use common::clone;
   use yew_hooks::use_async;
 
-  let board_not_found = use_state(|| false);
-  let board_layout = use_local_storage_unf::<BoardLayout>(format!("layout of board {id}"));
-  let board_meta = use_local_storage_unf::<BoardDetails>(format!("meatdata of board {id}"));
-  let load_layout = use_async::<_, (), !>(clone!(board_layout, board_not_found; async move {
-    let has_layout = board_layout.is_some();
-    let rep = retry(Duration::from_secs(4), clone!(board_meta; move || {
-      let mut req = Request::get(&api(&format!("board/{id}/layout")));
-      if let (Some(meta), true) = (board_meta.as_ref(), has_layout) {
-        req = req.header("If-None-Match", &format!("\"{}\"", meta.version));
+  let board_not_found = use_state(|| None::<BoardNotFound>);
+  // the layout is stored along with its ETag so that it can be revalidated, a
+  // layout that came without one is fetched whole every time
+  let board_layout = use_local_storage_unf::<(Option<String>, BoardLayout)>(layout_key(&id.0));
+  let board_meta = use_local_storage_unf::<BoardDetails>(meta_key(&id.0));
+  // private boards need either the user's token or a grant from a share link
+  let grant = use_local_storage_unf::<String>(grant_key(&id.0));
+  let tokens = use_context::<Option<TokenPair>>().flatten();
+  let navi = use_navigator().unwrap();
+  let load_layout = use_async::<_, (), !>(clone!(board_layout, board_not_found, id; async move {
+    let etag = board_layout.as_ref().and_then(|(etag, _)| etag.clone());
+    let credentials = move |mut req: RequestBuilder| {
+      if let Some(tokens) = &tokens {
+        req = req.header("Authorization", &format!("Bearer {}", tokens.access_token));
+      }
+      if let Some(grant) = &*grant {
+        req = req.header("X-Share-Grant", grant);
+      }
+      req
+    };
+    let layout_url = api(&format!("boards/{id}/layout"));
+    let rep = retry(Duration::from_secs(4), clone!(credentials; move || {
+      let mut req = credentials(Request::get(&layout_url));
+      if let Some(etag) = &etag {
+        req = req.header("If-None-Match", etag);
       }
       ready(req)
     })).await;
+    // the board was moved and the old url kept as an alias, which the browser
+    // followed to .../boards/{new}/layout
+    if rep.redirected() {
+      let new_url = rep.url();
+      if let Some(new_id) = new_url.rsplit('/').nth(1).filter(|new_id| *new_id != id.0) {
+        board_moved(&id.0, new_id);
+        navi.replace(&Routes::Board{ id: new_id.to_string() });
+        return Ok(());
+      }
+    }
     if rep.status() == 304 {
       // not modified, use cached
     } else if rep.ok() {
-      board_layout.set(rep.json().await.unwrap());
+      let etag = rep.headers().get("ETag");
+      board_layout.set((etag, rep.json::<BoardLayout>().await.unwrap().upgrade()));
       // if there was a new layout, we know that there's a new version so we load that too.
       // This persists the unchanged shortcut on future layout fetches, but isn't required for
       // functionality so it's not a problem if a rerender slips between the two
-      let layout_req = || ready(Request::get(&api(&format!("board/{id}/layout"))));
-      board_meta.set(retry(Duration::from_secs(4), layout_req).await.json().await.unwrap());
-    } else if rep.status() == 404 {
-      board_not_found.set(true);
+      let meta_url = api(&format!("boards/{id}"));
+      let meta_req = || ready(credentials(Request::get(&meta_url)));
+      board_meta.set(retry(Duration::from_secs(4), meta_req).await.json().await.unwrap());
+    } else if rep.status() == 404 || rep.status() == 410 {
+      board_not_found.set(Some(BoardNotFound { trashed: rep.status() == 410 }));
       board_layout.delete();
       board_meta.delete();
     }
     Ok(())
   }));
   use_effect_with(id, move |_| load_layout.run());
-  if *board_not_found { Some(Err(BoardNotFound)) } else { board_layout.as_ref().cloned().map(Ok) }
+  match *board_not_found {
+    Some(not_found) => Some(Err(not_found)),
+    // the cached copy may have been written by an older release
+    None => board_layout.as_ref().map(|(_, layout)| Ok(layout.clone().upgrade())),
+  }
+}
+
+#[derive(PartialEq, Clone, Properties)]
+pub struct BVProps {
+  pub id: BoardUrl,
+}
+
+#[function_component(BoardView)]
+pub fn board_view(props: &BVProps) -> Html {
+  let board = use_board_layout(props.id.clone());
+  match board {
+    None => html!{ "Loading board..."},
+    Some(Err(BoardNotFound{ trashed: false })) => html!{ <NotFound typ={NotFoundTyp::Board} /> },
+    Some(Err(BoardNotFound{ trashed: true })) => html!{
+      <NotFound typ={NotFoundTyp::TrashedBoard} />
+    },
+    Some(Ok(BoardLayout::V2(v2))) => html!{ <BoardViewV2 layout={v2.clone()} /> },
+    Some(Ok(BoardLayout::V1(_))) => unreachable!("Layouts are upgraded when loaded"),
+  }
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
-pub struct BoardLayoutV1 {
-  sections: Vec<(String, Vec<Section>)>,
+#[derive(PartialEq, Clone, Properties)]
+pub struct SlugProps {
+  pub user: String,
+  pub slug: String,
+}
+
+/// A board found by the slug its owner gave it rather than its url
+#[function_component(SlugView)]
+pub fn slug_view(props: &SlugProps) -> Html {
+  use std::time::Duration;
+
+  use gloo_storage::{LocalStorage, Storage as _};
+  use yew_hooks::use_async;
+
+  let SlugProps { user, slug } = props.clone();
+  let tokens = use_context::<Option<TokenPair>>().flatten();
+  // remembered so that the board can be shown before the server answers
+  let resolved = use_local_storage_unf::<BoardUrl>(format!("board with slug {user}/{slug}"));
+  // the error status, once the slug turned out not to lead to a board
+  let failure = use_state(|| None::<u16>);
+  let resolve = use_async::<_, (), !>(clone!(resolved, failure, tokens; async move {
+    let details_url = api(&format!("b/{user}/{slug}"));
+    // private boards shared by link need the grant of the board the slug led
+    // to last time
+    let grant = (resolved.as_ref())
+      .and_then(|id| LocalStorage::get::<String>(grant_key(&id.0)).ok());
+    let rep = retry(Duration::from_secs(4), move || {
+      let mut req = Request::get(&details_url);
+      if let Some(tokens) = &tokens {
+        req = req.header("Authorization", &format!("Bearer {}", tokens.access_token));
+      }
+      if let Some(grant) = &grant {
+        req = req.header("X-Share-Grant", grant);
+      }
+      ready(req)
+    }).await;
+    if rep.ok() {
+      resolved.set(rep.json::<BoardDetails>().await.unwrap().url);
+    } else {
+      failure.set(Some(rep.status()));
+      resolved.delete();
+    }
+    Ok(())
+  }));
+  use_effect_with(props.clone(), move |_| resolve.run());
+  match (&*resolved, *failure) {
+    (_, Some(404)) => html!{ <NotFound typ={NotFoundTyp::Board} /> },
+    (_, Some(410)) => html!{ <NotFound typ={NotFoundTyp::TrashedBoard} /> },
+    (_, Some(status)) => html!{ <p>{format!("Failed to load the board ({status})")}</p> },
+    (Some(id), None) => board_page(id.clone(), tokens.as_ref()),
+    (None, None) => html!{ "Loading board..." },
+  }
+}
+
+#[derive(PartialEq, Clone, Properties)]
+pub struct BVV2Props {
+  pub layout: BoardLayoutV2,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
-pub struct Section {
-  title: String,
-  items: Vec<SectionItem>,
+#[function_component(BoardViewV2)]
+pub fn board_view_v2(props: &BVV2Props) -> Html {
+  html! {
+    <div class="board">
+      {for props.layout.columns.iter().map(|col| html!{
+        <section class="column">
+          {if col.title.is_empty() { html!{} } else { html!{ <h2>{&col.title}</h2> } }}
+          {render_entries(&col.entries)}
+        </section>
+      })}
+    </div>
+  }
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
-pub struct SectionItem {
-  url: String,
-  name: String,
+fn render_entries(entries: &[Entry]) -> Html {
+  html! {
+    <ul class="entries">
+      {for entries.iter().map(|entry| match entry {
+        Entry::Separator => html!{ <li><hr /></li> },
+        Entry::Folder(folder) => html!{
+          <li class="folder">
+            <details open={!folder.collapsed}>
+              <summary>{&folder.title}</summary>
+              {render_entries(&folder.entries)}
+            </details>
+          </li>
+        },
+        Entry::Item(item) => html!{
+          <li><ItemView item={item.clone()} /></li>
+        },
+      })}
+    </ul>
+  }
 }
 
 #[derive(PartialEq, Clone, Properties)]
-pub struct BVProps {
-  pub id: i64,
+pub struct IVProps {
+  pub item: Item,
 }
 
-#[function_component(BoardView)]
-pub fn board_view(props: &BVProps) -> Html {
-  let board = use_board_layout(props.id);
-  match board {
-    None => html!{ "Loading board..."},
-    Some(Err(BoardNotFound)) => html!{ <NotFound typ={NotFoundTyp::Board} /> },
-    Some(Ok(BoardLayout::V1(v1))) => html!{ <BoardViewV1 layout={v1.clone()} /> }
+/// Icons of items and boards are either the url of an image or an emoji
+pub fn icon_view(icon: Option<&String>) -> Html {
+  match icon {
+    None => html! {},
+    Some(url) if url.contains(':') => html! { <img class="icon" src={url.clone()} alt="" /> },
+    Some(emoji) => html! { <span class="icon">{emoji}</span> },
+  }
+}
+
+#[function_component(ItemView)]
+pub fn item_view(props: &IVProps) -> Html {
+  let item = &props.item;
+  let icon = icon_view(item.icon.as_ref());
+  let style = item.color.as_ref().map(|c| format!("border-color: {c}"));
+  let description = item.description.clone();
+  let body = match &item.kind {
+    ItemKind::Link { url, name } => html! {
+      <a href={url.clone()} title={description.clone()}>{icon}{name}</a>
+    },
+    ItemKind::Note { text } => html! { <p class="note">{icon}{text}</p> },
+    ItemKind::Board { url, name } => html! {
+      <Link<Routes> to={Routes::Board{ id: url.to_string() }}>{icon}{name}</Link<Routes>>
+    },
+    ItemKind::Search { template, name } => html! {
+      <SearchBox template={template.clone()} name={name.clone()} {icon} />
+    },
+  };
+  html! {
+    <div class="item" {style}>
+      {body}
+      {description.map(|d| html!{ <small class="description">{d}</small> })}
+    </div>
   }
 }
 
 #[derive(PartialEq, Clone, Properties)]
-pub struct BVV1Props {
-  pub layout: BoardLayoutV1,
+pub struct SBProps {
+  pub template: String,
+  pub name: String,
+  pub icon: Html,
 }
 
-#[function_component(BoardViewV1)]
-pub fn board_view_v1(props: &BVV1Props) -> Html {
+#[function_component(SearchBox)]
+pub fn search_box(props: &SBProps) -> Html {
+  let input = use_node_ref();
+  let template = props.template.clone();
+  let onsubmit = clone!(input; move |e: SubmitEvent| {
+    e.prevent_default();
+    let query = input.cast::<HtmlInputElement>().unwrap().value();
+    let query = String::from(encode_uri_component(&query));
+    let target = template.replace(SEARCH_PLACEHOLDER, &query);
+    gloo_utils::window().location().set_href(&target).unwrap();
+  });
   html! {
-    <div>{"hello"}</div>
+    <form class="search" {onsubmit}>
+      <label>{props.icon.clone()}{&props.name}<input type="search" ref={input} /></label>
+    </form>
   }
 }
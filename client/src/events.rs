@@ -0,0 +1,66 @@
+use common::{BoardDetails, BoardUrl, ChangeEvent, TokenPair};
+use gloo_console::log;
+use gloo_storage::{LocalStorage, Storage as _};
+use web_sys::js_sys::Date;
+use web_sys::wasm_bindgen::closure::Closure;
+use web_sys::wasm_bindgen::JsCast;
+use web_sys::{EventSource, MessageEvent};
+use yew::{hook, use_effect_with};
+
+use crate::api;
+use crate::board::{meta_key, prefetch_boards};
+use crate::util::set_local_storage;
+
+/// Local storage key set to the time the user's view layout was last found to
+/// have changed on the server
+pub const LAYOUT_CHANGED: &str = "layout changed";
+
+/// Listen to changes of the boards and, if logged in, the user's view layout,
+/// refreshing the cached copies so that every tab shows the latest versions
+#[hook]
+pub fn use_change_events(boards: Vec<BoardUrl>, tokens: Option<TokenPair>) {
+  // the stream is reopened with each new access token, since the server only
+  // checks it when subscribing
+  use_effect_with((boards, tokens), |(boards, tokens)| {
+    let urls = boards.iter().map(|board| &board.0[..]).collect::<Vec<_>>().join(",");
+    let mut events_url = api(&format!("events?boards={urls}"));
+    if let Some(tokens) = tokens {
+      events_url += &format!("&layout=true&accessToken={}", tokens.access_token);
+    }
+    let source = EventSource::new(&events_url).unwrap();
+    let (boards, tokens) = (boards.clone(), tokens.clone());
+    let onmessage: Closure<dyn Fn(MessageEvent)> = Closure::new(move |ev: MessageEvent| {
+      let Some(data) = ev.data().as_string() else { return };
+      let stale = match serde_json::from_str::<ChangeEvent>(&data) {
+        Err(e) => {
+          log!(format!("Unexpected change event {data}: {e}"));
+          return;
+        },
+        Ok(ChangeEvent::Board { url, version }) => {
+          let held = LocalStorage::get::<BoardDetails>(meta_key(&url.0)).map(|d| d.version);
+          if held.is_ok_and(|held| held == version) {
+            return;
+          }
+          vec![url]
+        },
+        // the batch reports the move and the new version
+        Ok(ChangeEvent::Moved { from, .. }) => vec![from],
+        Ok(ChangeEvent::Deleted { url }) => vec![url],
+        Ok(ChangeEvent::Layout) => {
+          set_local_storage(LAYOUT_CHANGED, Date::now());
+          return;
+        },
+        Ok(ChangeEvent::Missed) => {
+          set_local_storage(LAYOUT_CHANGED, Date::now());
+          boards.clone()
+        },
+      };
+      yew::platform::spawn_local(prefetch_boards(stale, tokens.clone()));
+    });
+    source.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    move || {
+      source.close();
+      drop(onmessage);
+    }
+  });
+}
@@ -1,26 +1,29 @@
-use common::{clone, FreshBoard, NewBoardForm, TokenPair};
+use std::collections::HashMap;
+
+use common::layout::{BoardLayout, ViewLayout, ViewLayoutV1};
+use common::versioned::Versioned;
+use common::{
+  clone, BoardDetails, BoardUrl, FreshBoard, NewBoardForm, StarterTemplate, Templates, TokenPair,
+  Visibility,
+};
 use gloo_console::log;
 use gloo_net::http::Request;
-use serde::{Deserialize, Serialize};
+use gloo_storage::{LocalStorage, Storage as _};
 use yew::suspense::use_future;
-use yew::{function_component, hook, html, use_context, use_effect_with, Html, Properties};
+use yew::{
+  classes, function_component, hook, html, use_context, use_effect_with, use_mut_ref, Html,
+  Properties,
+};
 use yew_hooks::use_async;
 use yew_router::prelude::*;
 
 use crate::app::Routes;
-use crate::board::{BoardLayout, BoardView};
+use crate::board::{icon_view, meta_key, prefetch_boards, BoardView, MOVED_BOARDS};
+use crate::events::{use_change_events, LAYOUT_CHANGED};
+use crate::misc_yew::local_date;
 use crate::rtr_client::{authenticated, tok_claims};
 use crate::util::{use_local_storage_unf, UseLocalStorageUnfHandle};
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
-pub enum ViewLayout {
-  V1(ViewLayoutV1),
-}
-
-impl Default for ViewLayout {
-  fn default() -> Self { Self::V1(ViewLayoutV1 { top: Vec::new(), groups: Vec::new() }) }
-}
-
 fn get_user_id(tok: &str) -> i64 { tok_claims(tok).get("user_id").unwrap().parse().unwrap() }
 
 #[hook]
@@ -28,6 +31,24 @@ fn use_layout_ls() -> UseLocalStorageUnfHandle<(i64, ViewLayout)> {
   use_local_storage_unf::<(i64, ViewLayout)>("current layout".to_string())
 }
 
+/// Point the layout at the current url of boards that were found to have moved
+fn follow_moves(layout: &mut ViewLayout, moved: &HashMap<String, String>) -> bool {
+  let mut changed = false;
+  let mut follow = |board: &mut BoardUrl| {
+    if let Some(to) = moved.get(&board.0) {
+      *board = BoardUrl(to.clone());
+      changed = true;
+    }
+  };
+  match layout {
+    ViewLayout::V1(v1) => {
+      v1.top.iter_mut().for_each(&mut follow);
+      v1.groups.iter_mut().flat_map(|(_, boards)| boards).for_each(&mut follow);
+    },
+  }
+  changed
+}
+
 #[hook]
 fn use_layout(tok: &String) -> Option<ViewLayout> {
   use std::time::Duration;
@@ -39,7 +60,10 @@ fn use_layout(tok: &String) -> Option<ViewLayout> {
 
   let layout = use_layout_ls();
   let current_user: i64 = get_user_id(&tok);
-  let load_layout = use_async::<_, (), !>(clone!(tok, layout; async move {
+  // written by board views when they get redirected, so reload when it changes
+  let moved = LocalStorage::get::<HashMap<String, String>>(MOVED_BOARDS).unwrap_or_default();
+  let changed_elsewhere = use_local_storage_unf::<f64>(LAYOUT_CHANGED.to_string());
+  let load_layout = use_async::<_, (), !>(clone!(tok, layout, moved; async move {
     loop {
       match authenticated(Request::get, Some(&tok), "layout").send().await {
         Err(e) => {
@@ -50,24 +74,26 @@ fn use_layout(tok: &String) -> Option<ViewLayout> {
         Ok(rep) if !rep.ok() => panic!("Unexpected error while getting layout: {rep:?}"),
         Ok(rep) => {
           let text = rep.text().await.unwrap();
-          let data = match &*text {
+          let mut data = match &*text {
             "" => ViewLayout::default(),
-            _ => serde_json::from_str(&text).unwrap()
+            _ => serde_json::from_str::<ViewLayout>(&text).unwrap().upgrade()
           };
+          let saved = !follow_moves(&mut data, &moved)
+            || authenticated(Request::post, Some(&tok), "layout")
+              .json(&data).unwrap().send().await.is_ok_and(|rep| rep.ok());
+          match saved {
+            true => LocalStorage::delete(MOVED_BOARDS),
+            false => log!("Failed to save layout with moved boards, will retry next time"),
+          }
           layout.set((get_user_id(&tok), data));
           break Ok(())
         }
       }
     }
   }));
-  use_effect_with(current_user, move |_| load_layout.run());
-  layout.as_ref().filter(|(id, _)| *id == current_user).map(|(_, lo)| lo.clone())
-}
-
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
-pub struct ViewLayoutV1 {
-  pub top: Vec<i64>,
-  pub groups: Vec<(String, Vec<i64>)>,
+  use_effect_with((current_user, moved, *changed_elsewhere), move |_| load_layout.run());
+  // the cached copy may have been written by an older release
+  layout.as_ref().filter(|(id, _)| *id == current_user).map(|(_, lo)| lo.clone().upgrade())
 }
 
 #[derive(PartialEq, Clone, Properties)]
@@ -80,11 +106,29 @@ pub fn default_board(props: &DBProps) -> Html {
   let layout_ls = use_layout_ls();
   let layout = use_layout(&props.access_token);
   let current_user: i64 = get_user_id(&props.access_token);
-  let add_home_board = use_async(clone!(props, layout, layout_ls; async move {
+  // the starter template picked for the first board, read when creating it
+  let starter = use_mut_ref(|| None::<StarterTemplate>);
+  let templates = use_async(clone!(props; async move {
+    let rep = authenticated(Request::get, Some(&props.access_token), "templates")
+      .send()
+      .await
+      .map_err(|e| e.to_string())?;
+    rep.json::<Templates>().await.map_err(|e| e.to_string())
+  }));
+  use_effect_with((), clone!(templates; move |_| templates.run()));
+  let add_home_board = use_async(clone!(props, layout, layout_ls, starter; async move {
     let mut layout = layout.unwrap();
-    let board_layout = serde_json::to_string(&BoardLayout::default()).unwrap();
+    let (name, board_layout) = match starter.borrow().clone() {
+      Some(StarterTemplate { name, layout, .. }) => (name, layout),
+      None => ("Home".to_string(), BoardLayout::default()),
+    };
     let rep = authenticated(Request::post, Some(&props.access_token), "new_board")
-      .json(&NewBoardForm { layout: board_layout, name: "Home".to_string(), public_mut: true })
+      .json(&NewBoardForm {
+        layout: serde_json::to_string(&board_layout).unwrap(),
+        name,
+        public_mut: true,
+        visibility: Visibility::Unlisted,
+      })
       .unwrap()
       .send()
       .await
@@ -112,10 +156,31 @@ pub fn default_board(props: &DBProps) -> Html {
         Some(fst) => html! { <Redirect<Routes> to={Routes::Board{ id: fst.to_string() }} /> },
         None if add_home_board.loading => html!{ <p>{"Creating first board..."}</p> },
         None if add_home_board.data.is_some() => html!{ <p>{"Failed to commit first board!"}</p> },
-        None => {
-          add_home_board.run();
-          html!{ <p>{"Preparing to create first board..."}</p> }
-        }
+        None => match &templates.data {
+          Some(Templates { starters, .. }) if !starters.is_empty() => html! {
+            <main class="starters">
+              <h1>{"Pick a starting point for your first board"}</h1>
+              {for starters.iter().cloned().map(|tmpl| {
+                let onclick = clone!(starter, add_home_board, tmpl; move |_| {
+                  *starter.borrow_mut() = Some(tmpl.clone());
+                  add_home_board.run();
+                });
+                html! {
+                  <button {onclick}>
+                    <h2>{&tmpl.name}</h2>
+                    <p>{&tmpl.description}</p>
+                  </button>
+                }
+              })}
+            </main>
+          },
+          None if templates.error.is_none() => html!{ <p>{"Loading templates..."}</p> },
+          // without templates the first board starts empty
+          _ => {
+            add_home_board.run();
+            html!{ <p>{"Preparing to create first board..."}</p> }
+          },
+        },
       }
     },
   }
@@ -124,17 +189,30 @@ pub fn default_board(props: &DBProps) -> Html {
 #[derive(PartialEq, Clone, Properties)]
 pub struct LVProps {
   pub access_token: String,
-  pub board_id: i64,
+  pub board_id: BoardUrl,
 }
 
 #[function_component(LayoutView)]
 pub fn layout_view(props: &LVProps) -> Html {
   let tp = use_context::<TokenPair>();
-  let layout = use_layout(&tp.unwrap().access_token);
+  let layout = use_layout(&tp.as_ref().unwrap().access_token);
+  let mut boards = match &layout {
+    None => Vec::new(),
+    Some(ViewLayout::V1(v1)) =>
+      (v1.top.iter().chain(v1.groups.iter().flat_map(|(_, boards)| boards))).cloned().collect(),
+  };
+  if !boards.contains(&props.board_id) {
+    boards.push(props.board_id.clone());
+  }
+  // warm the cache of every board of the layout, not just the one on screen
+  use_effect_with(boards.clone(), clone!(tp; move |boards| {
+    yew::platform::spawn_local(prefetch_boards(boards.clone(), tp));
+  }));
+  use_change_events(boards, tp);
   match layout.as_ref() {
-    None => html! { <BoardView id={props.board_id} /> },
+    None => html! { <BoardView id={props.board_id.clone()} /> },
     Some(ViewLayout::V1(v1)) => html! {
-      <LayoutViewV1 layout={v1.clone()} board_id={props.board_id} />
+      <LayoutViewV1 layout={v1.clone()} board_id={props.board_id.clone()} />
     },
   }
 }
@@ -142,15 +220,83 @@ pub fn layout_view(props: &LVProps) -> Html {
 #[derive(PartialEq, Clone, Properties)]
 struct LVV1Props {
   pub layout: ViewLayoutV1,
-  pub board_id: i64,
+  pub board_id: BoardUrl,
 }
 
 #[function_component(LayoutViewV1)]
 fn layout_view_v1(props: &LVV1Props) -> Html {
+  let tp = use_context::<TokenPair>();
+  let current_user = tp.as_ref().map(|tp| get_user_id(&tp.access_token));
+  let links = |boards: &[BoardUrl]| {
+    html! {
+      <ul>
+        {for boards.iter().map(|url| html! {
+          <SidebarBoard
+            url={url.clone()}
+            current={*url == props.board_id}
+            {current_user}
+          />
+        })}
+      </ul>
+    }
+  };
+  html! {
+    <div class="layout">
+      <nav class="sidebar">
+        {links(&props.layout.top)}
+        {for props.layout.groups.iter().map(|(name, boards)| html! {
+          <section>
+            <h3>{name}</h3>
+            {links(boards)}
+          </section>
+        })}
+      </nav>
+      <BoardView id={props.board_id.clone()} />
+    </div>
+  }
+}
+
+#[derive(PartialEq, Clone, Properties)]
+struct SBProps {
+  pub url: BoardUrl,
+  /// The board on screen, which gets its details shown
+  pub current: bool,
+  pub current_user: Option<i64>,
+}
+
+/// A board of the layout, as far as its cached details tell. Boards that
+/// haven't been fetched yet show their url.
+#[function_component(SidebarBoard)]
+fn sidebar_board(props: &SBProps) -> Html {
+  let meta = use_local_storage_unf::<BoardDetails>(meta_key(&props.url.0));
+  let link = |name: Html| {
+    html! {
+      <Link<Routes> to={Routes::Board{ id: props.url.to_string() }}>{name}</Link<Routes>>
+    }
+  };
+  let Some(details) = meta.as_ref() else {
+    return html! { <li>{link(html! { {&props.url.0} })}</li> };
+  };
+  let style = details.color.as_ref().map(|c| format!("border-color: {c}"));
+  let class = classes!(props.current.then_some("current"));
+  let name = html! { <>{icon_view(details.icon.as_ref())}{&details.name}</> };
+  let about = props.current.then(|| {
+    let by_you = details.updated_by.is_some() && details.updated_by == props.current_user;
+    html! {
+      <div class="about">
+        {details.description.as_ref().map(|d| html! { <p class="description">{d}</p> })}
+        <small>{format!("Created {}", local_date(details.created_at))}</small>
+        <small>
+          {format!("Updated {}", local_date(details.updated_at))}
+          {if by_you { " by you" } else { "" }}
+        </small>
+      </div>
+    }
+  });
   html! {
-    <>
-      <div>{"hello from the default layout"}</div>
-      <BoardView id={props.board_id} />
-    </>
+    <li {class} {style} title={details.description.clone()}>
+      {link(name)}
+      {about}
+    </li>
   }
 }
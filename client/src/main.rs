@@ -5,10 +5,12 @@ mod about;
 mod app;
 mod auth;
 mod board;
+mod events;
 mod layout;
 mod misc_yew;
 mod not_found;
 mod rtr_client;
+mod share;
 mod util;
 
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
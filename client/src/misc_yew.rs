@@ -2,6 +2,12 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use web_sys::js_sys::Date;
 use web_sys::js_sys::Math::random;
+use web_sys::wasm_bindgen::JsValue;
 
 pub fn u32rand() -> u32 { (random() * u32::MAX as f64).floor() as u32 }
 pub fn now() -> SystemTime { UNIX_EPOCH + Duration::from_secs_f64(Date::now() / 1000f64) }
+/// Show a timestamp from the server as a date in the user's locale
+pub fn local_date(secs: i64) -> String {
+  let date = Date::new(&JsValue::from_f64(secs as f64 * 1000f64));
+  date.to_locale_date_string("default", &JsValue::UNDEFINED).into()
+}
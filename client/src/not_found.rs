@@ -4,12 +4,20 @@ use yew::prelude::*;
 pub enum NotFoundTyp {
   Route,
   Board,
+  TrashedBoard,
 }
 impl NotFoundTyp {
   pub fn name(&self) -> &str {
     match self {
       Self::Route => "Route",
-      Self::Board => "Board",
+      Self::Board | Self::TrashedBoard => "Board",
+    }
+  }
+
+  pub fn status(&self) -> u16 {
+    match self {
+      Self::Route | Self::Board => 404,
+      Self::TrashedBoard => 410,
     }
   }
 
@@ -17,6 +25,7 @@ impl NotFoundTyp {
     match self {
       Self::Route => "The route couldn't be resolved with the app's routing scheme",
       Self::Board => "This board doesn't exist; it might have been deleted or moved",
+      Self::TrashedBoard => "This board was deleted, but its owner can still restore it",
     }
   }
 }
@@ -30,7 +39,7 @@ pub struct NotFoundProps {
 pub fn not_found(props: &NotFoundProps) -> Html {
   html! {
     <main>
-      <h1>{"404: "}{props.typ.name()}{" not found"}</h1>
+      <h1>{props.typ.status()}{": "}{props.typ.name()}{" not found"}</h1>
       <p>{props.typ.description()}</p>
     </main>
   }
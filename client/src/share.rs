@@ -0,0 +1,48 @@
+use common::{clone, ShareRedemption};
+use gloo_net::http::Request;
+use gloo_storage::{LocalStorage, Storage as _};
+use yew::{function_component, html, use_effect_with, Html, Properties};
+use yew_hooks::use_async;
+use yew_router::prelude::*;
+
+use crate::api;
+use crate::app::Routes;
+
+/// Local storage key of the share grant for a board
+pub fn grant_key(board: &str) -> String { format!("share grant of board {board}") }
+
+#[derive(PartialEq, Clone, Properties)]
+pub struct SVProps {
+  pub token: String,
+}
+
+/// Redeem a share link, remember the grant and move on to the board
+#[function_component(ShareView)]
+pub fn share_view(props: &SVProps) -> Html {
+  let token = props.token.clone();
+  let redeem = use_async(async move {
+    let rep = Request::post(&api(&format!("shares/{token}/redeem")))
+      .send()
+      .await
+      .map_err(|e| e.to_string())?;
+    if !rep.ok() {
+      return Err(rep.text().await.unwrap_or_default());
+    }
+    let redemption = rep.json::<ShareRedemption>().await.unwrap();
+    LocalStorage::set(grant_key(&redemption.board_url.0), &redemption.grant).unwrap();
+    Ok(redemption)
+  });
+  use_effect_with(props.token.clone(), clone!(redeem; move |_| redeem.run()));
+  match (&redeem.data, &redeem.error) {
+    (Some(r), _) => html! {
+      <Redirect<Routes> to={Routes::Board{ id: r.board_url.to_string() }} />
+    },
+    (_, Some(e)) => html! {
+      <main>
+        <h1>{"This share link doesn't work"}</h1>
+        <p>{e}</p>
+      </main>
+    },
+    (None, None) => html! { <p>{"Opening shared board..."}</p> },
+  }
+}
@@ -33,6 +33,16 @@ pub async fn retry<Fut: Future<Output = RequestBuilder>>(
   }
 }
 
+/// Store a value and let every [use_local_storage_unf] hook with this key know,
+/// which the browser only does for other tabs
+pub fn set_local_storage<T: Serialize>(key: &str, value: T) {
+  if LocalStorage::set(key, value).is_ok() {
+    let steve = StorageEvent::new("storage").unwrap_throw();
+    steve.init_storage_event_with_can_bubble_and_cancelable_and_key("storage", false, false, Some(key));
+    window().dispatch_event(&steve).unwrap_throw();
+  }
+}
+
 pub struct UseLocalStorageUnfHandle<T> {
   inner: Option<T>,
   latest: Rc<RefCell<Option<T>>>,
@@ -43,11 +53,7 @@ impl<T> UseLocalStorageUnfHandle<T> {
   pub fn set(&self, value: T) where T: Serialize + Clone {
     let cur_key = self.key.current();
     let cur_key = cur_key.as_ref().borrow();
-    if LocalStorage::set(&*cur_key, value.clone()).is_ok() {
-      let steve = StorageEvent::new("storage").unwrap_throw();
-      steve.init_storage_event_with_can_bubble_and_cancelable_and_key("storage", false, false, Some(&*cur_key));
-      window().dispatch_event(&steve).unwrap_throw();
-    }
+    set_local_storage(&cur_key, value)
   }
   pub fn delete(&self) {
     LocalStorage::delete(&*self.key.current().as_ref().borrow())
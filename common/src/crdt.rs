@@ -0,0 +1,250 @@
+//! Board layouts as Automerge documents, so that concurrent edits merge instead
+//! of failing on a stale version. The document holds the layout in the shape
+//! of its JSON form, with entries matched by id when a new layout is written
+//! into it, so that edits to different entries never touch the same objects.
+
+use std::collections::HashSet;
+
+use automerge::transaction::Transactable;
+use automerge::{AutoCommit, AutoSerde, AutomergeError, ObjId, ObjType, Prop, ReadDoc, Value};
+use serde_json::{Map, Number, Value as Json};
+
+use crate::layout::{BoardLayout, BoardLayoutV2, Entry};
+
+/// Start a document holding `layout`
+pub fn new_doc(layout: &BoardLayoutV2) -> Result<AutoCommit, AutomergeError> {
+  let mut doc = AutoCommit::new();
+  reconcile(&mut doc, layout)?;
+  Ok(doc)
+}
+
+/// Read the layout out of a document. Two peers may each move the same entry,
+/// leaving a copy of it in both places, in which case only the first is kept.
+/// Fails if concurrent edits left the document in a shape that isn't a layout,
+/// like an item that's both a link and a note.
+pub fn hydrate(doc: &AutoCommit) -> Result<BoardLayoutV2, serde_json::Error> {
+  let json = serde_json::to_value(AutoSerde::from(doc))?;
+  match serde_json::from_value::<BoardLayout>(json)? {
+    BoardLayout::V2(mut v2) => {
+      let mut seen = HashSet::new();
+      for column in &mut v2.columns {
+        dedupe(&mut column.entries, &mut seen);
+      }
+      Ok(v2)
+    },
+    BoardLayout::V1(_) => Err(serde::de::Error::custom("Documents only hold V2 layouts")),
+  }
+}
+
+fn dedupe(entries: &mut Vec<Entry>, seen: &mut HashSet<u64>) {
+  entries.retain(|entry| entry.id().is_none_or(|id| seen.insert(id)));
+  for entry in entries {
+    if let Entry::Folder(folder) = entry {
+      dedupe(&mut folder.entries, seen);
+    }
+  }
+}
+
+/// Change the document so that it holds `layout`, touching as little of it as
+/// possible. Moving an entry deletes and reinserts it.
+pub fn reconcile(doc: &mut AutoCommit, layout: &BoardLayoutV2) -> Result<(), AutomergeError> {
+  let json = serde_json::to_value(BoardLayout::V2(layout.clone())).unwrap();
+  let Json::Object(map) = json else { unreachable!("Layouts serialize to objects") };
+  reconcile_map(doc, &automerge::ROOT, &map)?;
+  doc.commit();
+  Ok(())
+}
+
+/// The id of an entry, which in JSON is `{"Item": {"id": ..}}`, so that lists
+/// of entries can be matched up by it. Columns and separators have none.
+fn json_key(value: &Json) -> Option<u64> {
+  let Json::Object(map) = value else { return None };
+  let (_, inner) = map.iter().next()?;
+  inner.get("id")?.as_u64()
+}
+
+fn doc_key(doc: &AutoCommit, list: &ObjId, index: usize) -> Option<u64> {
+  let (Value::Object(ObjType::Map), entry) = doc.get(list, index).ok()?? else { return None };
+  let key = doc.keys(&entry).next()?;
+  let (Value::Object(ObjType::Map), inner) = doc.get(&entry, key).ok()?? else { return None };
+  doc.get(&inner, "id").ok()??.0.to_u64()
+}
+
+fn reconcile_map(
+  doc: &mut AutoCommit,
+  obj: &ObjId,
+  map: &Map<String, Json>,
+) -> Result<(), AutomergeError> {
+  let stale = doc.keys(obj).filter(|key| !map.contains_key(key)).collect::<Vec<_>>();
+  for key in stale {
+    doc.delete(obj, key)?;
+  }
+  for (key, value) in map {
+    reconcile_at(doc, obj, key.as_str().into(), value)?;
+  }
+  Ok(())
+}
+
+/// Walks the new list, making each position of the document's list hold the
+/// same element: either it already does, or the entry there was deleted, or
+/// the new element is inserted before it. What's left past the end is stale.
+fn reconcile_list(doc: &mut AutoCommit, obj: &ObjId, list: &[Json]) -> Result<(), AutomergeError> {
+  let keys = list.iter().filter_map(json_key).collect::<HashSet<_>>();
+  for (i, value) in list.iter().enumerate() {
+    loop {
+      if doc.length(obj) <= i {
+        insert(doc, obj, i, value)?;
+        break;
+      }
+      let key = doc_key(doc, obj, i);
+      if key == json_key(value) {
+        reconcile_at(doc, obj, i.into(), value)?;
+        break;
+      }
+      if key.is_some_and(|key| !keys.contains(&key)) {
+        doc.delete(obj, i)?;
+        continue;
+      }
+      insert(doc, obj, i, value)?;
+      break;
+    }
+  }
+  while list.len() < doc.length(obj) {
+    doc.delete(obj, list.len())?;
+  }
+  Ok(())
+}
+
+fn reconcile_at(
+  doc: &mut AutoCommit,
+  obj: &ObjId,
+  prop: Prop,
+  value: &Json,
+) -> Result<(), AutomergeError> {
+  let current = doc.get(obj, prop.clone())?;
+  match (value, current) {
+    (Json::Object(map), Some((Value::Object(ObjType::Map), child))) =>
+      reconcile_map(doc, &child, map),
+    (Json::Array(list), Some((Value::Object(ObjType::List), child))) =>
+      reconcile_list(doc, &child, list),
+    (Json::Object(map), _) => {
+      let child = doc.put_object(obj, prop, ObjType::Map)?;
+      reconcile_map(doc, &child, map)
+    },
+    (Json::Array(list), _) => {
+      let child = doc.put_object(obj, prop, ObjType::List)?;
+      reconcile_list(doc, &child, list)
+    },
+    (scalar, Some((Value::Scalar(current), _))) if *current == scalar_value(scalar) => Ok(()),
+    (scalar, _) => doc.put(obj, prop, scalar_value(scalar)),
+  }
+}
+
+fn insert(
+  doc: &mut AutoCommit,
+  obj: &ObjId,
+  index: usize,
+  value: &Json,
+) -> Result<(), AutomergeError> {
+  match value {
+    Json::Object(map) => {
+      let child = doc.insert_object(obj, index, ObjType::Map)?;
+      reconcile_map(doc, &child, map)
+    },
+    Json::Array(list) => {
+      let child = doc.insert_object(obj, index, ObjType::List)?;
+      reconcile_list(doc, &child, list)
+    },
+    scalar => doc.insert(obj, index, scalar_value(scalar)),
+  }
+}
+
+fn scalar_value(value: &Json) -> automerge::ScalarValue {
+  use automerge::ScalarValue as S;
+  match value {
+    Json::Null => S::Null,
+    Json::Bool(b) => S::Boolean(*b),
+    Json::String(s) => S::Str(s.as_str().into()),
+    Json::Number(n) => number(n),
+    Json::Object(_) | Json::Array(_) => unreachable!("Objects aren't scalars"),
+  }
+}
+
+fn number(n: &Number) -> automerge::ScalarValue {
+  use automerge::ScalarValue as S;
+  match (n.as_u64(), n.as_i64()) {
+    (Some(u), _) => S::Uint(u),
+    (None, Some(i)) => S::Int(i),
+    (None, None) => S::F64(n.as_f64().unwrap()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::layout::{Column, Item, ItemKind};
+
+  fn note(id: u64, text: &str) -> Entry {
+    Entry::Item(Item::new(id, ItemKind::Note { text: text.to_string() }))
+  }
+
+  fn layout(columns: Vec<Vec<Entry>>) -> BoardLayoutV2 {
+    let columns = (columns.into_iter().enumerate())
+      .map(|(i, entries)| Column { title: format!("Column {i}"), entries })
+      .collect();
+    BoardLayoutV2 { columns }
+  }
+
+  /// Write `next` into a copy of a document holding `base`, as a peer would
+  fn edited(base: &mut AutoCommit, next: &BoardLayoutV2) -> AutoCommit {
+    let mut peer = base.fork();
+    reconcile(&mut peer, next).unwrap();
+    peer
+  }
+
+  fn merged(mut ours: AutoCommit, mut theirs: AutoCommit) -> BoardLayoutV2 {
+    ours.merge(&mut theirs).unwrap();
+    hydrate(&ours).unwrap()
+  }
+
+  #[test]
+  fn reorder_keeps_concurrent_edits() {
+    let mut doc = new_doc(&layout(vec![vec![note(1, "a"), note(2, "b"), note(3, "c")]])).unwrap();
+    let reordered = edited(&mut doc, &layout(vec![vec![note(3, "c"), note(1, "a"), note(2, "b")]]));
+    let renamed =
+      edited(&mut doc, &layout(vec![vec![note(1, "a"), note(2, "edited"), note(3, "c")]]));
+    let expected = layout(vec![vec![note(3, "c"), note(1, "a"), note(2, "edited")]]);
+    assert_eq!(merged(reordered, renamed), expected);
+  }
+
+  #[test]
+  fn delete_keeps_concurrent_edits() {
+    let mut doc = new_doc(&layout(vec![vec![note(1, "a"), note(2, "b"), note(3, "c")]])).unwrap();
+    let deleted = edited(&mut doc, &layout(vec![vec![note(1, "a"), note(3, "c")]]));
+    let renamed =
+      edited(&mut doc, &layout(vec![vec![note(1, "a"), note(2, "b"), note(3, "edited")]]));
+    let expected = layout(vec![vec![note(1, "a"), note(3, "edited")]]);
+    assert_eq!(merged(deleted, renamed), expected);
+  }
+
+  #[test]
+  fn move_between_columns() {
+    let mut doc = new_doc(&layout(vec![vec![note(1, "a"), note(2, "b")], vec![]])).unwrap();
+    let next = layout(vec![vec![note(1, "a")], vec![note(2, "b")]]);
+    reconcile(&mut doc, &next).unwrap();
+    assert_eq!(hydrate(&doc).unwrap(), next);
+  }
+
+  #[test]
+  fn concurrent_moves_keep_one_copy() {
+    let base = layout(vec![vec![note(1, "a"), note(2, "b")], vec![], vec![]]);
+    let mut doc = new_doc(&base).unwrap();
+    let left = edited(&mut doc, &layout(vec![vec![note(1, "a")], vec![note(2, "b")], vec![]]));
+    let right = edited(&mut doc, &layout(vec![vec![note(1, "a")], vec![], vec![note(2, "b")]]));
+    let result = merged(left, right);
+    let mut copies = 0;
+    result.walk(|_, entry| copies += usize::from(entry.id() == Some(2)));
+    assert_eq!(copies, 1);
+    assert_eq!(result.columns[0].entries, vec![note(1, "a")]);
+  }
+}
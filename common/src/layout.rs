@@ -0,0 +1,527 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::versioned::Versioned;
+use crate::BoardUrl;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BoardLayout {
+  V1(BoardLayoutV1),
+  V2(BoardLayoutV2),
+}
+impl Default for BoardLayout {
+  fn default() -> Self { Self::V2(BoardLayoutV2 { columns: Vec::new() }) }
+}
+impl Versioned for BoardLayout {
+  const MIGRATIONS: &'static [fn(Self) -> Self] = &[board_v1_to_v2];
+  fn version(&self) -> usize {
+    match self {
+      Self::V1(_) => 1,
+      Self::V2(_) => 2,
+    }
+  }
+}
+
+/// Every group becomes a column and every section a folder in it, so nothing is
+/// lost. Ids are handed out in document order.
+fn board_v1_to_v2(layout: BoardLayout) -> BoardLayout {
+  let BoardLayout::V1(v1) = layout else { unreachable!("Migration called with the wrong version") };
+  let mut next_id = 0;
+  let mut fresh_id = || {
+    next_id += 1;
+    next_id
+  };
+  let columns = (v1.sections.into_iter())
+    .map(|(title, secs)| Column {
+      title,
+      entries: (secs.into_iter())
+        .map(|sec| {
+          Entry::Folder(Folder {
+            id: fresh_id(),
+            title: sec.title,
+            collapsed: false,
+            entries: (sec.items.into_iter())
+              .map(|item| {
+                Entry::Item(Item::new(fresh_id(), ItemKind::Link {
+                  url: item.url,
+                  name: item.name,
+                }))
+              })
+              .collect(),
+          })
+        })
+        .collect(),
+    })
+    .collect();
+  BoardLayout::V2(BoardLayoutV2 { columns })
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoardLayoutV1 {
+  pub sections: Vec<(String, Vec<Section>)>,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Section {
+  pub title: String,
+  pub items: Vec<SectionItem>,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectionItem {
+  pub url: String,
+  pub name: String,
+}
+
+/// Columns are laid out side by side, each holding a tree of entries
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoardLayoutV2 {
+  pub columns: Vec<Column>,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Column {
+  pub title: String,
+  pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Entry {
+  Folder(Folder),
+  Item(Item),
+  Separator,
+}
+
+/// Folders and items carry an id that is unique within the board and survives
+/// edits, so that they can be told apart from similar looking ones
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Folder {
+  pub id: u64,
+  pub title: String,
+  #[serde(default)]
+  pub collapsed: bool,
+  pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Item {
+  pub id: u64,
+  pub kind: ItemKind,
+  /// An emoji or the URL of an image
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub icon: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  /// Accent color in `#rrggbb` form
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub color: Option<String>,
+}
+impl Item {
+  pub fn new(id: u64, kind: ItemKind) -> Self {
+    Self { id, kind, icon: None, description: None, color: None }
+  }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ItemKind {
+  Link {
+    url: String,
+    name: String,
+  },
+  Note {
+    text: String,
+  },
+  /// Link to another board
+  Board {
+    url: BoardUrl,
+    name: String,
+  },
+  /// Search box that opens `template` with [SEARCH_PLACEHOLDER] replaced by the
+  /// query
+  Search {
+    template: String,
+    name: String,
+  },
+}
+
+pub const SEARCH_PLACEHOLDER: &str = "{query}";
+
+impl BoardLayoutV2 {
+  /// Visit every entry depth first, along with its JSON pointer
+  pub fn walk(&self, mut f: impl FnMut(&str, &Entry)) {
+    fn go(path: &str, entries: &[Entry], f: &mut impl FnMut(&str, &Entry)) {
+      for (i, entry) in entries.iter().enumerate() {
+        let path = format!("{path}/{i}");
+        f(&path, entry);
+        if let Entry::Folder(folder) = entry {
+          go(&format!("{path}/Folder/entries"), &folder.entries, f);
+        }
+      }
+    }
+    for (i, col) in self.columns.iter().enumerate() {
+      go(&format!("/V2/columns/{i}/entries"), &col.entries, &mut f);
+    }
+  }
+
+  /// An id that isn't used by any entry yet
+  pub fn fresh_id(&self) -> u64 {
+    let mut max = 0;
+    self.walk(|_, entry| match entry {
+      Entry::Folder(Folder { id, .. }) | Entry::Item(Item { id, .. }) => max = max.max(*id),
+      Entry::Separator => (),
+    });
+    max + 1
+  }
+
+  pub fn find(&self, id: u64) -> Option<&Entry> {
+    fn go(entries: &[Entry], id: u64) -> Option<&Entry> {
+      entries.iter().find_map(|entry| match entry {
+        _ if entry.id() == Some(id) => Some(entry),
+        Entry::Folder(folder) => go(&folder.entries, id),
+        _ => None,
+      })
+    }
+    self.columns.iter().find_map(|col| go(&col.entries, id))
+  }
+
+  pub fn find_mut(&mut self, id: u64) -> Option<&mut Entry> {
+    fn go(entries: &mut [Entry], id: u64) -> Option<&mut Entry> {
+      for entry in entries {
+        if entry.id() == Some(id) {
+          return Some(entry);
+        }
+        if let Entry::Folder(folder) = entry {
+          if let Some(found) = go(&mut folder.entries, id) {
+            return Some(found);
+          }
+        }
+      }
+      None
+    }
+    self.columns.iter_mut().find_map(|col| go(&mut col.entries, id))
+  }
+
+  /// Remove an entry wherever it is, along with its contents if it's a folder
+  pub fn take(&mut self, id: u64) -> Option<Entry> {
+    fn go(entries: &mut Vec<Entry>, id: u64) -> Option<Entry> {
+      if let Some(i) = entries.iter().position(|entry| entry.id() == Some(id)) {
+        return Some(entries.remove(i));
+      }
+      entries.iter_mut().find_map(|entry| match entry {
+        Entry::Folder(folder) => go(&mut folder.entries, id),
+        _ => None,
+      })
+    }
+    self.columns.iter_mut().find_map(|col| go(&mut col.entries, id))
+  }
+
+  /// Insert an entry at a placement. The ids in it are left as they are, see
+  /// [Entry::renumber] for entries that come from elsewhere.
+  pub fn place(&mut self, at: &Placement, entry: Entry) -> Result<(), PlaceError> {
+    if let Parent::Folder(parent) = at.parent {
+      if entry.id() == Some(parent) || entry.find(parent).is_some() {
+        return Err(PlaceError::IntoItself);
+      }
+    }
+    let entries = match at.parent {
+      Parent::Column(i) => &mut self.columns.get_mut(i).ok_or(PlaceError::NoColumn(i))?.entries,
+      Parent::Folder(id) => match self.find_mut(id) {
+        Some(Entry::Folder(folder)) => &mut folder.entries,
+        _ => return Err(PlaceError::NoFolder(id)),
+      },
+    };
+    let index = at.index.unwrap_or(entries.len()).min(entries.len());
+    entries.insert(index, entry);
+    Ok(())
+  }
+}
+
+impl Entry {
+  /// Separators have no id
+  pub fn id(&self) -> Option<u64> {
+    match self {
+      Self::Folder(Folder { id, .. }) | Self::Item(Item { id, .. }) => Some(*id),
+      Self::Separator => None,
+    }
+  }
+
+  /// Find an entry inside a folder, not counting the folder itself
+  pub fn find(&self, id: u64) -> Option<&Entry> {
+    let Self::Folder(folder) = self else { return None };
+    folder.entries.iter().find_map(|entry| match entry {
+      _ if entry.id() == Some(id) => Some(entry),
+      _ => entry.find(id),
+    })
+  }
+
+  /// Give the entry and everything in it ids from `fresh` onwards, so that it
+  /// can join a board whose ids it might clash with. Returns the next free id.
+  pub fn renumber(&mut self, mut fresh: u64) -> u64 {
+    match self {
+      Self::Item(item) => {
+        item.id = fresh;
+        fresh += 1;
+      },
+      Self::Folder(folder) => {
+        folder.id = fresh;
+        fresh += 1;
+        for entry in &mut folder.entries {
+          fresh = entry.renumber(fresh);
+        }
+      },
+      Self::Separator => (),
+    }
+    fresh
+  }
+}
+
+/// Where an entry goes among the entries of a column or folder. Without an
+/// index, or with one past the end, it goes after the last one.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Placement {
+  pub parent: Parent,
+  #[serde(default)]
+  pub index: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Parent {
+  /// Columns are identified by their position
+  Column(usize),
+  Folder(u64),
+}
+
+/// [BoardLayoutV2::place] found nowhere to put the entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlaceError {
+  NoColumn(usize),
+  NoFolder(u64),
+  /// A folder can't be put in itself or in one of its subfolders
+  IntoItself,
+}
+impl fmt::Display for PlaceError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::NoColumn(i) => write!(f, "The board has no column {i}"),
+      Self::NoFolder(id) => write!(f, "The board has no folder with the id {id}"),
+      Self::IntoItself => write!(f, "A folder can't be moved into itself"),
+    }
+  }
+}
+
+/// The arrangement of boards a user sees around the current one
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ViewLayout {
+  V1(ViewLayoutV1),
+}
+impl Default for ViewLayout {
+  fn default() -> Self { Self::V1(ViewLayoutV1 { top: Vec::new(), groups: Vec::new() }) }
+}
+impl Versioned for ViewLayout {
+  const MIGRATIONS: &'static [fn(Self) -> Self] = &[];
+  fn version(&self) -> usize {
+    match self {
+      Self::V1(_) => 1,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewLayoutV1 {
+  pub top: Vec<BoardUrl>,
+  pub groups: Vec<(String, Vec<BoardUrl>)>,
+}
+
+/// Parse a stored layout of any version and, if it isn't in the newest format,
+/// return the upgraded serialization. Unparseable text is left alone.
+pub fn upgrade_stored<T: Versioned + Serialize + for<'de> Deserialize<'de>>(
+  text: &str,
+) -> Option<String> {
+  let value = serde_json::from_str::<T>(text).ok().filter(|v| !v.is_latest())?;
+  Some(serde_json::to_string(&value.upgrade()).unwrap())
+}
+
+/// URL schemes a bookmark may use. Anything else, notably `javascript:` and
+/// `data:`, could run code in the context of the page the board is shown on.
+pub const URL_SCHEMES: &[&str] = &["http", "https", "ftp", "mailto"];
+pub const MAX_GROUPS: usize = 50;
+pub const MAX_SECTIONS: usize = 200;
+pub const MAX_ITEMS: usize = 2000;
+pub const MAX_TITLE_LEN: usize = 200;
+pub const MAX_URL_LEN: usize = 2048;
+pub const MAX_NOTE_LEN: usize = 10000;
+/// How deep folders may be nested
+pub const MAX_DEPTH: usize = 8;
+
+/// A problem with one field of a submitted document
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldError {
+  /// JSON pointer to the offending field, empty if the document as a whole is
+  /// wrong
+  pub path: String,
+  pub message: String,
+}
+impl FieldError {
+  pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+    Self { path: path.into(), message: message.into() }
+  }
+}
+
+/// Parse a board layout of any version, upgrade it to the newest one and check
+/// it against the limits above
+pub fn parse_board_layout(text: &str) -> Result<BoardLayout, Vec<FieldError>> {
+  let layout = serde_json::from_str::<BoardLayout>(text)
+    .map_err(|e| vec![FieldError::new("", format!("Not a board layout: {e}"))])?
+    .upgrade();
+  let errors = layout.validate();
+  if errors.is_empty() {
+    Ok(layout)
+  } else {
+    Err(errors)
+  }
+}
+
+pub(crate) fn check_text(path: String, text: &str, errors: &mut Vec<FieldError>) {
+  if MAX_TITLE_LEN < text.chars().count() {
+    errors.push(FieldError::new(path, format!("Longer than {MAX_TITLE_LEN} characters")));
+  } else if text.chars().any(char::is_control) {
+    errors.push(FieldError::new(path, "Contains control characters"));
+  }
+}
+
+pub(crate) fn check_note(path: String, text: &str, errors: &mut Vec<FieldError>) {
+  if MAX_NOTE_LEN < text.chars().count() {
+    errors.push(FieldError::new(path, format!("Longer than {MAX_NOTE_LEN} characters")));
+  } else if text.chars().any(|c| c.is_control() && !"\n\t".contains(c)) {
+    errors.push(FieldError::new(path, "Contains control characters"));
+  }
+}
+
+pub(crate) fn check_color(path: String, color: &str, errors: &mut Vec<FieldError>) {
+  let hex =
+    color.strip_prefix('#').filter(|h| h.len() == 6 && h.chars().all(|c| c.is_ascii_hexdigit()));
+  if hex.is_none() {
+    errors.push(FieldError::new(path, "Must be a color of the form #rrggbb"));
+  }
+}
+
+pub(crate) fn check_icon(path: String, icon: &str, errors: &mut Vec<FieldError>) {
+  match icon.contains(':') {
+    true => check_url(path, icon, errors),
+    false => check_text(path, icon, errors),
+  }
+}
+
+fn check_url(path: String, url: &str, errors: &mut Vec<FieldError>) {
+  if MAX_URL_LEN < url.len() {
+    return errors.push(FieldError::new(path, format!("Longer than {MAX_URL_LEN} bytes")));
+  }
+  if url.chars().any(|c| c.is_control() || c.is_whitespace()) {
+    return errors.push(FieldError::new(path, "Contains whitespace or control characters"));
+  }
+  let scheme = url.split_once(':').map(|(s, _)| s).filter(|s| {
+    s.starts_with(|c: char| c.is_ascii_alphabetic())
+      && s.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+  });
+  match scheme {
+    None => errors.push(FieldError::new(path, "Must be an absolute URL")),
+    Some(s) if !URL_SCHEMES.contains(&&*s.to_ascii_lowercase()) => errors
+      .push(FieldError::new(path, format!("The scheme must be one of {}", URL_SCHEMES.join(", ")))),
+    Some(_) => (),
+  }
+}
+
+impl BoardLayout {
+  /// Collect every problem with the layout so they can be reported at once.
+  /// Older versions are checked in their upgraded form.
+  pub fn validate(&self) -> Vec<FieldError> {
+    match self {
+      Self::V2(v2) => v2.validate(),
+      old => old.clone().upgrade().validate(),
+    }
+  }
+}
+
+impl BoardLayoutV2 {
+  fn validate(&self) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if MAX_GROUPS < self.columns.len() {
+      errors.push(FieldError::new("/V2/columns", format!("More than {MAX_GROUPS} columns")));
+    }
+    for (i, col) in self.columns.iter().enumerate() {
+      check_text(format!("/V2/columns/{i}/title"), &col.title, &mut errors);
+    }
+    let (mut folders, mut items) = (0, 0);
+    let mut ids = HashSet::new();
+    self.walk(|path, entry| {
+      // every level of nesting adds "/Folder/entries/n" to the path
+      if MAX_DEPTH < path.matches("/Folder/").count() {
+        errors.push(FieldError::new(path, format!("Nested deeper than {MAX_DEPTH} folders")));
+      }
+      let id = match entry {
+        Entry::Separator => return,
+        Entry::Folder(folder) => {
+          folders += 1;
+          check_text(format!("{path}/Folder/title"), &folder.title, &mut errors);
+          folder.id
+        },
+        Entry::Item(item) => {
+          items += 1;
+          item.validate(&format!("{path}/Item"), &mut errors);
+          item.id
+        },
+      };
+      if !ids.insert(id) {
+        errors.push(FieldError::new(path, format!("The id {id} is used more than once")));
+      }
+    });
+    if MAX_SECTIONS < folders {
+      errors.push(FieldError::new("/V2/columns", format!("More than {MAX_SECTIONS} folders")));
+    }
+    if MAX_ITEMS < items {
+      errors.push(FieldError::new("/V2/columns", format!("More than {MAX_ITEMS} items")));
+    }
+    errors
+  }
+}
+
+impl Item {
+  fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+    match &self.kind {
+      ItemKind::Link { url, name } => {
+        check_text(format!("{path}/kind/Link/name"), name, errors);
+        check_url(format!("{path}/kind/Link/url"), url, errors);
+      },
+      ItemKind::Note { text } => check_note(format!("{path}/kind/Note/text"), text, errors),
+      ItemKind::Board { url, name } => {
+        check_text(format!("{path}/kind/Board/name"), name, errors);
+        if !url.is_well_formed() {
+          errors.push(FieldError::new(format!("{path}/kind/Board/url"), "Not a board url"));
+        }
+      },
+      ItemKind::Search { template, name } => {
+        check_text(format!("{path}/kind/Search/name"), name, errors);
+        let template_path = format!("{path}/kind/Search/template");
+        if !template.contains(SEARCH_PLACEHOLDER) {
+          let message = format!("Must contain {SEARCH_PLACEHOLDER} where the query goes");
+          errors.push(FieldError::new(template_path.clone(), message));
+        }
+        check_url(template_path, template, errors);
+      },
+    }
+    if let Some(icon) = &self.icon {
+      check_icon(format!("{path}/icon"), icon, errors);
+    }
+    if let Some(description) = &self.description {
+      check_note(format!("{path}/description"), description, errors);
+    }
+    if let Some(color) = &self.color {
+      check_color(format!("{path}/color"), color, errors);
+    }
+  }
+}
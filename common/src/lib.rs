@@ -1,5 +1,14 @@
+#[cfg(feature = "crdt")]
+pub mod crdt;
+pub mod layout;
+pub mod merge;
+pub mod versioned;
+
+use std::fmt;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use layout::{BoardLayout, Entry, FieldError, ItemKind, Placement};
+use merge::MergeConflict;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -25,6 +34,52 @@ pub struct TokenPair {
 pub fn epoch_secs(st: SystemTime) -> u64 { st.duration_since(UNIX_EPOCH).unwrap().as_secs() }
 pub fn from_epoch_secs(secs: u64) -> SystemTime { UNIX_EPOCH + Duration::from_secs(secs) }
 
+/// Public identifier of a board, 128 random bits in URL-safe base64. Boards
+/// created before that have decimal identifiers, which used to be stored as
+/// numbers, so numbers are accepted when deserializing.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(transparent)]
+pub struct BoardUrl(pub String);
+impl<'de> Deserialize<'de> for BoardUrl {
+  fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+      Text(String),
+      Legacy(i64),
+    }
+    Ok(match Repr::deserialize(deserializer)? {
+      Repr::Text(s) => Self(s),
+      Repr::Legacy(n) => Self(n.to_string()),
+    })
+  }
+}
+impl fmt::Display for BoardUrl {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(&self.0) }
+}
+impl From<String> for BoardUrl {
+  fn from(value: String) -> Self { Self(value) }
+}
+impl BoardUrl {
+  /// Whether this could identify a board, so that garbage isn't sent to the
+  /// server
+  pub fn is_well_formed(&self) -> bool {
+    (1..=64).contains(&self.0.len())
+      && self.0.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+  }
+}
+
+pub const MAX_SLUG_LEN: usize = 64;
+
+/// Slugs are meant to be typed, so they are limited to lowercase letters,
+/// digits and inner dashes
+pub fn is_valid_slug(slug: &str) -> bool {
+  (1..=MAX_SLUG_LEN).contains(&slug.len())
+    && slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    && !slug.starts_with('-')
+    && !slug.ends_with('-')
+}
+
 #[macro_export]
 macro_rules! clone {
   ($($n:ident),+; $body:expr) => (
@@ -39,18 +94,82 @@ macro_rules! clone {
 #[serde(rename_all = "camelCase")]
 pub struct BoardDetails {
   pub id: i64,
+  pub url: BoardUrl,
+  /// Reachable as `/b/{owner name}/{slug}` as well
+  pub slug: Option<String>,
   pub name: String,
   pub version: i32,
-  pub owner_id: i64,
+  /// Missing for boards created without an account that nobody has claimed
+  pub owner_id: Option<i64>,
   pub public_mut: bool,
+  pub visibility: Visibility,
+  pub is_template: bool,
+  // defaults for copies cached by clients before these were added
+  #[serde(default)]
+  pub description: Option<String>,
+  /// An emoji, or the url of an image, like the icons of items
+  #[serde(default)]
+  pub icon: Option<String>,
+  /// Accent color, as `#rrggbb`
+  #[serde(default)]
+  pub color: Option<String>,
+  #[serde(default)]
+  pub created_at: i64,
+  #[serde(default)]
+  pub updated_at: i64,
+  /// Who made the current version, missing for anonymous edits
+  #[serde(default)]
+  pub updated_by: Option<i64>,
 }
 
+/// Changes to the settings of a board. The owner can't be changed this way,
+/// see [TransferForm], and is rejected rather than ignored. An empty
+/// description, icon or color removes it.
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct BoardPatch {
   pub name: Option<String>,
   pub public_mut: Option<bool>,
-  pub owner_id: Option<i64>,
+  pub visibility: Option<Visibility>,
+  #[serde(default)]
+  pub is_template: Option<bool>,
+  #[serde(default)]
+  pub description: Option<String>,
+  #[serde(default)]
+  pub icon: Option<String>,
+  #[serde(default)]
+  pub color: Option<String>,
+}
+impl BoardPatch {
+  /// Check the metadata against the limits items are held to
+  pub fn validate(&self) -> Vec<FieldError> {
+    use layout::{check_color, check_icon, check_note};
+    let mut errors = Vec::new();
+    fn set(field: &Option<String>) -> Option<&str> { field.as_deref().filter(|t| !t.is_empty()) }
+    if let Some(description) = set(&self.description) {
+      check_note("/description".to_string(), description, &mut errors);
+    }
+    if let Some(icon) = set(&self.icon) {
+      check_icon("/icon".to_string(), icon, &mut errors);
+    }
+    if let Some(color) = set(&self.color) {
+      check_color("/color".to_string(), color, &mut errors);
+    }
+    errors
+  }
+}
+
+/// Body of a `412 Precondition Failed` or `409 Conflict` answer to a board
+/// write, describing the version the write should have been based on
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BoardConflict {
+  pub version: i32,
+  /// Only included for layout edits
+  pub layout: Option<String>,
+  /// Why a write based on an older version couldn't be merged
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub conflicts: Vec<MergeConflict>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -59,11 +178,497 @@ pub struct NewBoardForm {
   pub name: String,
   pub public_mut: bool,
   pub layout: String,
+  #[serde(default)]
+  pub visibility: Visibility,
+}
+
+/// Optional body of a fork request
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForkForm {
+  /// Defaults to the name of the original
+  #[serde(default)]
+  pub name: Option<String>,
+  /// Defaults to the visibility of the original
+  #[serde(default)]
+  pub visibility: Option<Visibility>,
+}
+
+/// Who may read a board. Publicly mutable boards are only writable by those who
+/// can read them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Visibility {
+  /// Readable by anyone and may be listed
+  Public,
+  /// Readable by anyone who knows the url
+  #[default]
+  Unlisted,
+  /// Readable only by the owner and members
+  Private,
+}
+impl Visibility {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Public => "public",
+      Self::Unlisted => "unlisted",
+      Self::Private => "private",
+    }
+  }
+
+  pub fn parse(name: &str) -> Option<Self> {
+    [Self::Public, Self::Unlisted, Self::Private].into_iter().find(|v| v.as_str() == name)
+  }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FreshBoard {
   pub id: i64,
-  pub url: i64,
+  pub url: BoardUrl,
+}
+
+/// A board created without an account. The edit key isn't stored anywhere
+/// the server can show it again.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnonymousBoard {
+  pub id: i64,
+  pub url: BoardUrl,
+  pub edit_key: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EditKey {
+  pub edit_key: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionDetails {
+  pub version: i32,
+  /// Missing for anonymous edits and for history that predates revision
+  /// tracking
+  pub author_id: Option<i64>,
+  pub author_name: Option<String>,
+  pub created_at: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DiffStatus {
+  Added,
+  Removed,
+  Changed,
+}
+
+/// A bookmark that differs between two revisions. Bookmarks are identified by
+/// their url within a section, so a changed bookmark is one that was renamed.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemDiff {
+  pub url: String,
+  pub status: DiffStatus,
+  pub old_name: Option<String>,
+  pub new_name: Option<String>,
+}
+
+/// A section that differs between two revisions, identified by its column and
+/// the slash separated path of folders leading to it
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SectionDiff {
+  pub group: String,
+  pub title: String,
+  pub status: DiffStatus,
+  pub items: Vec<ItemDiff>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutDiff {
+  pub from: i32,
+  pub to: i32,
+  /// Only the sections that changed
+  pub sections: Vec<SectionDiff>,
+}
+
+/// What a member of a board may do. Each role includes the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BoardRole {
+  Viewer,
+  Editor,
+  /// May also rename the board and invite or remove members
+  Manager,
+}
+impl BoardRole {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Viewer => "viewer",
+      Self::Editor => "editor",
+      Self::Manager => "manager",
+    }
+  }
+
+  pub fn parse(name: &str) -> Option<Self> {
+    [Self::Viewer, Self::Editor, Self::Manager].into_iter().find(|r| r.as_str() == name)
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteForm {
+  /// Username of the invitee
+  pub name: String,
+  pub role: BoardRole,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberDetails {
+  pub user_id: i64,
+  pub name: String,
+  pub role: BoardRole,
+  /// False while the invitation is pending
+  pub accepted: bool,
+}
+
+/// A pending invitation as seen by the invitee
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvitationDetails {
+  pub board_url: BoardUrl,
+  pub board_name: String,
+  pub role: BoardRole,
+  pub invited_by: Option<String>,
+  pub created_at: i64,
+}
+
+/// Offer a board to another user, who becomes its owner once they accept
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferForm {
+  /// Username of the recipient
+  pub name: String,
+}
+
+/// A pending ownership transfer, as seen by either side
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferDetails {
+  pub board_url: BoardUrl,
+  pub board_name: String,
+  pub from: String,
+  pub to: String,
+  pub created_at: i64,
+}
+
+/// Notifications are all about transfers so far, the names they're sent and
+/// stored under say so
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum NotificationKind {
+  /// Sent to the recipient of a transfer
+  #[serde(rename = "transferRequested")]
+  Requested,
+  #[serde(rename = "transferCancelled")]
+  Cancelled,
+  /// Sent to the previous owner
+  #[serde(rename = "transferAccepted")]
+  Accepted,
+  #[serde(rename = "transferDeclined")]
+  Declined,
+}
+impl NotificationKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Requested => "transfer_requested",
+      Self::Cancelled => "transfer_cancelled",
+      Self::Accepted => "transfer_accepted",
+      Self::Declined => "transfer_declined",
+    }
+  }
+
+  pub fn parse(name: &str) -> Option<Self> {
+    [Self::Requested, Self::Cancelled, Self::Accepted, Self::Declined]
+      .into_iter()
+      .find(|k| k.as_str() == name)
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationDetails {
+  pub id: i64,
+  pub kind: NotificationKind,
+  pub board_url: BoardUrl,
+  pub board_name: String,
+  /// Name of the other user involved
+  pub other: Option<String>,
+  pub read: bool,
+  pub created_at: i64,
+}
+
+/// What holders of a share link may do with the board
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ShareMode {
+  Read,
+  Edit,
+}
+impl ShareMode {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Read => "read",
+      Self::Edit => "edit",
+    }
+  }
+
+  pub fn parse(name: &str) -> Option<Self> {
+    [Self::Read, Self::Edit].into_iter().find(|m| m.as_str() == name)
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewShareForm {
+  pub mode: ShareMode,
+  pub label: Option<String>,
+  /// Seconds since the epoch after which the link stops working
+  pub expires_at: Option<i64>,
+  /// Number of times the link can be redeemed
+  pub max_uses: Option<i32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareDetails {
+  pub token: String,
+  pub mode: ShareMode,
+  pub label: Option<String>,
+  pub expires_at: Option<i64>,
+  pub max_uses: Option<i32>,
+  pub uses: i32,
+  pub created_at: i64,
+}
+
+/// Answer to redeeming a share link. The grant is sent back in the
+/// `X-Share-Grant` header to access the board.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareRedemption {
+  pub board_url: BoardUrl,
+  pub mode: ShareMode,
+  pub grant: String,
+  pub expires_at: i64,
+}
+
+/// Former url of a board that redirects to the current one
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AliasDetails {
+  pub url: BoardUrl,
+  /// The alias stops redirecting after this time, if set
+  pub expires_at: Option<i64>,
+  pub created_at: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlugForm {
+  pub slug: String,
+}
+
+/// Slug reserved for a board by its owner
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlugDetails {
+  pub slug: String,
+  /// Former slugs keep resolving to the board until they are released
+  pub current: bool,
+  pub created_at: i64,
+}
+
+/// Board in its owner's trash
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashedBoard {
+  pub id: i64,
+  pub url: BoardUrl,
+  pub name: String,
+  pub deleted_at: i64,
+  /// When the board will be purged, unless the trash is kept forever
+  pub purge_at: Option<i64>,
+}
+
+/// Starting point for new boards provided by the server
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StarterTemplate {
+  pub key: String,
+  pub name: String,
+  pub description: String,
+  pub layout: BoardLayout,
+}
+
+/// Board its owner marked as a template, used by forking it
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateBoard {
+  pub url: BoardUrl,
+  pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Templates {
+  pub starters: Vec<StarterTemplate>,
+  pub boards: Vec<TemplateBoard>,
+}
+
+/// Entry of a board listing
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BoardListing {
+  #[serde(flatten)]
+  pub details: BoardDetails,
+  /// Set if the board is shared with the user rather than owned by them
+  pub role: Option<BoardRole>,
+  pub item_count: usize,
+}
+
+/// One page of a board listing
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BoardPage {
+  pub boards: Vec<BoardListing>,
+  /// Pass as `cursor` to get the next page, absent on the last one
+  pub next_cursor: Option<String>,
+}
+
+/// A board the client wants, with the version it already holds if any
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchEntry {
+  pub url: BoardUrl,
+  pub version: Option<i32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchRequest {
+  pub boards: Vec<BatchEntry>,
+}
+
+/// What became of a board asked for in a batch. Boards the client already has
+/// the current version of are left out.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum BatchResult {
+  Changed {
+    details: BoardDetails,
+    layout: BoardLayout,
+  },
+  /// The url is an alias of a moved board. If the board changed as well, it
+  /// also appears as changed under its new url.
+  Moved {
+    from: BoardUrl,
+    to: BoardUrl,
+  },
+  /// Deleted or not accessible, like a `404` or `410` for a single board
+  Gone {
+    url: BoardUrl,
+    trashed: bool,
+  },
+  /// The stored layout predates validation and isn't one the server can read,
+  /// the board can still be fetched on its own
+  Unreadable {
+    url: BoardUrl,
+  },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchResponse {
+  pub boards: Vec<BatchResult>,
+}
+
+/// An entry to add to a board. Any ids in it are replaced by fresh ones.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewEntryForm {
+  pub at: Placement,
+  pub entry: Entry,
+}
+
+/// Changes to an entry, tagged like [Entry]. The contents of a folder are
+/// edited entry by entry.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum EntryPatch {
+  Folder(FolderPatch),
+  Item(ItemPatch),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderPatch {
+  pub title: Option<String>,
+  pub collapsed: Option<bool>,
+}
+
+/// An empty string removes the icon, description or color
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemPatch {
+  pub kind: Option<ItemKind>,
+  pub icon: Option<String>,
+  pub description: Option<String>,
+  pub color: Option<String>,
+}
+
+/// Move an entry elsewhere in its board, or to another board if `board` is set
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveEntryForm {
+  #[serde(default)]
+  pub board: Option<BoardUrl>,
+  pub at: Placement,
+}
+
+/// An entry as stored after a write, and the version of the board holding it.
+/// Entries added or moved to another board get fresh ids.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryDetails {
+  pub board: BoardUrl,
+  pub version: i32,
+  pub entry: Entry,
+}
+
+/// Pushed by `GET /events` when a watched board or the user's view layout
+/// changes. Only versions are sent, the changes themselves are fetched as
+/// usual.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ChangeEvent {
+  /// The board is at this version now. Also sent for every watched board when
+  /// the stream opens, so that reconnecting catches up.
+  Board {
+    url: BoardUrl,
+    version: i32,
+  },
+  Moved {
+    from: BoardUrl,
+    to: BoardUrl,
+  },
+  /// Moved to the trash, or not accessible
+  Deleted {
+    url: BoardUrl,
+  },
+  /// The user's view layout was saved, maybe from another tab
+  Layout,
+  /// The stream fell behind and dropped events, so everything watched should
+  /// be revalidated
+  Missed,
 }
@@ -0,0 +1,372 @@
+//! Three-way merge of board layouts, for writes based on a version that has
+//! since been replaced. Entries are matched by id and columns by position.
+//! Both sides' changes to different entries are kept, as are changes only one
+//! side made. An entry changed on both sides, or changed on one and deleted on
+//! the other, is a conflict. Moves never conflict, ours win.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::layout::{BoardLayoutV2, Column, Entry, Folder, Parent};
+
+/// Something both sides changed in different ways. Entries are given without
+/// their children, `None` meaning that the side deleted it.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum MergeConflict {
+  /// Both sides changed the column titles, at least one by adding or removing
+  /// columns
+  Columns {
+    base: Vec<String>,
+    ours: Vec<String>,
+    theirs: Vec<String>,
+  },
+  /// Both sides renamed a column
+  Column {
+    index: usize,
+    base: String,
+    ours: String,
+    theirs: String,
+  },
+  Entry {
+    id: u64,
+    base: Option<Box<Entry>>,
+    ours: Option<Box<Entry>>,
+    theirs: Option<Box<Entry>>,
+  },
+}
+impl MergeConflict {
+  fn order(&self) -> (u8, u64) {
+    match self {
+      Self::Columns { .. } => (0, 0),
+      Self::Column { index, .. } => (1, *index as u64),
+      Self::Entry { id, .. } => (2, *id),
+    }
+  }
+}
+
+/// Separators have no id, so they're told apart by what precedes them
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+enum Key {
+  Id(u64),
+  Separator { parent: Parent, after: Option<u64>, nth: usize },
+}
+
+/// A layout as a set of entries without their children, each knowing where it
+/// is, so that the three versions can be compared entry by entry
+struct Flat {
+  nodes: HashMap<Key, (Parent, Entry)>,
+  /// The children of every column and folder, in order
+  order: HashMap<Parent, Vec<Key>>,
+  titles: Vec<String>,
+}
+impl Flat {
+  fn new(layout: &BoardLayoutV2) -> Self {
+    let mut flat = Flat { nodes: HashMap::new(), order: HashMap::new(), titles: Vec::new() };
+    for (i, col) in layout.columns.iter().enumerate() {
+      flat.titles.push(col.title.clone());
+      flat.add(Parent::Column(i), &col.entries);
+    }
+    flat
+  }
+
+  fn add(&mut self, parent: Parent, entries: &[Entry]) {
+    let (mut after, mut nth) = (None, 0);
+    let mut keys = Vec::with_capacity(entries.len());
+    for entry in entries {
+      let key = match entry.id() {
+        Some(id) => {
+          (after, nth) = (Some(id), 0);
+          Key::Id(id)
+        },
+        None => {
+          nth += 1;
+          Key::Separator { parent, after, nth }
+        },
+      };
+      keys.push(key);
+      self.nodes.insert(key, (parent, shallow(entry)));
+      if let Entry::Folder(folder) = entry {
+        self.add(Parent::Folder(folder.id), &folder.entries);
+      }
+    }
+    self.order.insert(parent, keys);
+  }
+
+  fn content(&self, key: &Key) -> Option<&Entry> { self.nodes.get(key).map(|(_, entry)| entry) }
+
+  fn boxed(&self, key: &Key) -> Option<Box<Entry>> { self.content(key).cloned().map(Box::new) }
+
+  /// Where an entry is: its parent and the entry before it
+  fn position(&self, key: &Key) -> Option<(Parent, Option<Key>)> {
+    let (parent, _) = self.nodes.get(key)?;
+    let siblings = &self.order[parent];
+    let i = siblings.iter().position(|k| k == key).unwrap();
+    Some((*parent, i.checked_sub(1).map(|i| siblings[i])))
+  }
+}
+
+/// An entry without its children
+fn shallow(entry: &Entry) -> Entry {
+  match entry {
+    Entry::Folder(folder) => Entry::Folder(Folder {
+      id: folder.id,
+      title: folder.title.clone(),
+      collapsed: folder.collapsed,
+      entries: Vec::new(),
+    }),
+    other => other.clone(),
+  }
+}
+
+/// The side that changed something wins, unless both did differently
+fn pick<T: PartialEq>(base: T, ours: T, theirs: T) -> Option<T> {
+  match () {
+    _ if ours == base => Some(theirs),
+    _ if theirs == base || ours == theirs => Some(ours),
+    _ => None,
+  }
+}
+
+fn ids(layout: &BoardLayoutV2) -> HashSet<u64> {
+  let mut ids = HashSet::new();
+  layout.walk(|_, entry| ids.extend(entry.id()));
+  ids
+}
+
+/// Both sides hand out ids from the same counter, so the entries they each
+/// added may share ids. Ours are given fresh ones.
+fn renumber_clashes(base: &BoardLayoutV2, ours: &mut BoardLayoutV2, theirs: &BoardLayoutV2) {
+  fn go(entries: &mut [Entry], clashes: &HashSet<u64>, fresh: &mut u64) {
+    for entry in entries {
+      let id = match entry {
+        Entry::Folder(folder) => {
+          go(&mut folder.entries, clashes, fresh);
+          &mut folder.id
+        },
+        Entry::Item(item) => &mut item.id,
+        Entry::Separator => continue,
+      };
+      if clashes.contains(id) {
+        *id = *fresh;
+        *fresh += 1;
+      }
+    }
+  }
+  let base_ids = ids(base);
+  let clashes = (ids(ours).intersection(&ids(theirs)).copied())
+    .filter(|id| !base_ids.contains(id))
+    .collect::<HashSet<_>>();
+  if clashes.is_empty() {
+    return;
+  }
+  let mut fresh = ours.fresh_id().max(theirs.fresh_id());
+  for col in &mut ours.columns {
+    go(&mut col.entries, &clashes, &mut fresh);
+  }
+}
+
+/// Merge the changes `ours` made to `base` into `theirs`, which also started
+/// from `base`
+pub fn merge(
+  base: &BoardLayoutV2,
+  ours: &BoardLayoutV2,
+  theirs: &BoardLayoutV2,
+) -> Result<BoardLayoutV2, Vec<MergeConflict>> {
+  let mut ours = ours.clone();
+  renumber_clashes(base, &mut ours, theirs);
+  let (b, o, t) = (Flat::new(base), Flat::new(&ours), Flat::new(theirs));
+  let mut conflicts = Vec::new();
+
+  let titles = match pick(&b.titles, &o.titles, &t.titles) {
+    Some(titles) => titles.clone(),
+    None if o.titles.len() == b.titles.len() && t.titles.len() == b.titles.len() =>
+      (b.titles.iter().zip(&o.titles).zip(&t.titles).enumerate())
+        .map(|(index, ((base, ours), theirs))| {
+          pick(base, ours, theirs).cloned().unwrap_or_else(|| {
+            conflicts.push(MergeConflict::Column {
+              index,
+              base: base.clone(),
+              ours: ours.clone(),
+              theirs: theirs.clone(),
+            });
+            theirs.clone()
+          })
+        })
+        .collect(),
+    None => {
+      conflicts.push(MergeConflict::Columns {
+        base: b.titles.clone(),
+        ours: o.titles.clone(),
+        theirs: t.titles.clone(),
+      });
+      t.titles.clone()
+    },
+  };
+
+  let keys = (b.nodes.keys().chain(o.nodes.keys()).chain(t.nodes.keys())).collect::<HashSet<_>>();
+  let mut nodes = HashMap::new();
+  // entries placed where ours put them, rather than where theirs are
+  let mut ours_placed = HashSet::new();
+  for key in keys {
+    let entry = match pick(b.content(key), o.content(key), t.content(key)) {
+      Some(Some(entry)) => entry.clone(),
+      Some(None) => continue,
+      None => {
+        if let Key::Id(id) = key {
+          conflicts.push(MergeConflict::Entry {
+            id: *id,
+            base: b.boxed(key),
+            ours: o.boxed(key),
+            theirs: t.boxed(key),
+          });
+        }
+        continue;
+      },
+    };
+    let ours_moved = o.position(key).is_some_and(|pos| Some(pos) != b.position(key));
+    let parent = match (ours_moved, o.position(key), t.position(key)) {
+      (true, Some((parent, _)), _) | (_, _, Some((parent, _))) => parent,
+      (_, Some((parent, _)), None) => parent,
+      (_, None, None) => unreachable!("Merged entries come from one side or the other"),
+    };
+    if ours_moved || t.position(key).is_none() {
+      ours_placed.insert(*key);
+    }
+    nodes.insert(*key, (parent, entry));
+  }
+
+  // siblings keep their order in theirs, then the entries ours moved or added
+  // go after whatever precedes them in ours
+  let mut order = HashMap::<Parent, Vec<Key>>::new();
+  for (parent, keys) in &t.order {
+    let kept = keys
+      .iter()
+      .filter(|key| !ours_placed.contains(key) && nodes.get(key).is_some_and(|(p, _)| p == parent));
+    order.insert(*parent, kept.copied().collect());
+  }
+  for (parent, keys) in &o.order {
+    for (i, key) in keys.iter().enumerate() {
+      if !ours_placed.contains(key) || nodes.get(key).map(|(p, _)| p) != Some(parent) {
+        continue;
+      }
+      let siblings = order.entry(*parent).or_default();
+      let after = keys[..i].iter().rev().find_map(|prev| siblings.iter().position(|k| k == prev));
+      siblings.insert(after.map_or(0, |i| i + 1), *key);
+    }
+  }
+
+  fn build(
+    parent: Parent,
+    order: &HashMap<Parent, Vec<Key>>,
+    nodes: &HashMap<Key, (Parent, Entry)>,
+    placed: &mut HashSet<Key>,
+  ) -> Vec<Entry> {
+    let keys = order.get(&parent).map_or(&[][..], |keys| &keys[..]);
+    (keys.iter())
+      .map(|key| {
+        placed.insert(*key);
+        let mut entry = nodes[key].1.clone();
+        if let Entry::Folder(folder) = &mut entry {
+          folder.entries = build(Parent::Folder(folder.id), order, nodes, placed);
+        }
+        entry
+      })
+      .collect()
+  }
+  let mut placed = HashSet::new();
+  let columns = (titles.into_iter().enumerate())
+    .map(|(i, title)| Column {
+      title,
+      entries: build(Parent::Column(i), &order, &nodes, &mut placed),
+    })
+    .collect();
+  // left out are entries whose folder or column the other side removed, and
+  // folders the two sides moved into each other
+  for key in nodes.keys().filter(|key| !placed.contains(key)) {
+    if let Key::Id(id) = key {
+      conflicts.push(MergeConflict::Entry {
+        id: *id,
+        base: b.boxed(key),
+        ours: o.boxed(key),
+        theirs: t.boxed(key),
+      });
+    }
+  }
+
+  match conflicts.is_empty() {
+    true => Ok(BoardLayoutV2 { columns }),
+    false => {
+      conflicts.sort_by_key(MergeConflict::order);
+      Err(conflicts)
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::layout::{Item, ItemKind};
+
+  fn note(id: u64, text: &str) -> Entry {
+    Entry::Item(Item::new(id, ItemKind::Note { text: text.to_string() }))
+  }
+
+  fn layout(columns: Vec<Vec<Entry>>) -> BoardLayoutV2 {
+    let columns = (columns.into_iter().enumerate())
+      .map(|(i, entries)| Column { title: format!("Column {i}"), entries })
+      .collect();
+    BoardLayoutV2 { columns }
+  }
+
+  #[test]
+  fn edits_to_the_same_item_conflict() {
+    let base = layout(vec![vec![note(1, "base"), note(2, "untouched")]]);
+    let ours = layout(vec![vec![note(1, "ours"), note(2, "untouched")]]);
+    let theirs = layout(vec![vec![note(1, "theirs"), note(2, "untouched")]]);
+    let conflicts = merge(&base, &ours, &theirs).unwrap_err();
+    assert_eq!(conflicts, vec![MergeConflict::Entry {
+      id: 1,
+      base: Some(Box::new(note(1, "base"))),
+      ours: Some(Box::new(note(1, "ours"))),
+      theirs: Some(Box::new(note(1, "theirs"))),
+    }]);
+  }
+
+  #[test]
+  fn same_edit_on_both_sides_merges() {
+    let base = layout(vec![vec![note(1, "base")]]);
+    let both = layout(vec![vec![note(1, "both")]]);
+    assert_eq!(merge(&base, &both, &both), Ok(both));
+  }
+
+  #[test]
+  fn move_and_edit_merge() {
+    let base = layout(vec![vec![note(1, "a"), note(2, "b")], vec![]]);
+    let moved = layout(vec![vec![note(1, "a")], vec![note(2, "b")]]);
+    let edited = layout(vec![vec![note(1, "a"), note(2, "edited")], vec![]]);
+    let expected = layout(vec![vec![note(1, "a")], vec![note(2, "edited")]]);
+    assert_eq!(merge(&base, &moved, &edited), Ok(expected.clone()));
+    assert_eq!(merge(&base, &edited, &moved), Ok(expected));
+  }
+
+  #[test]
+  fn separators_follow_what_precedes_them() {
+    let base = layout(vec![vec![note(1, "a"), Entry::Separator, note(2, "b")]]);
+    // ours adds one at the end, theirs removes the existing one
+    let ours = layout(vec![vec![note(1, "a"), Entry::Separator, note(2, "b"), Entry::Separator]]);
+    let theirs = layout(vec![vec![note(1, "a"), note(2, "b")]]);
+    let expected = layout(vec![vec![note(1, "a"), note(2, "b"), Entry::Separator]]);
+    assert_eq!(merge(&base, &ours, &theirs), Ok(expected));
+  }
+
+  #[test]
+  fn clashing_ids_of_added_items_are_renumbered() {
+    let base = layout(vec![vec![note(1, "a")]]);
+    let ours = layout(vec![vec![note(1, "a"), note(2, "ours")]]);
+    let theirs = layout(vec![vec![note(1, "a"), note(2, "theirs")]]);
+    let expected = layout(vec![vec![note(1, "a"), note(3, "ours"), note(2, "theirs")]]);
+    assert_eq!(merge(&base, &ours, &theirs), Ok(expected));
+  }
+}
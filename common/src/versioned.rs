@@ -0,0 +1,25 @@
+/// A document format that has gone through several versions, usually an enum
+/// with one variant per version. Stored copies may be of any version,
+/// [Versioned::upgrade] brings them up to date by applying every migration
+/// between their version and the newest one in order.
+///
+/// Introducing a new version means adding a variant and appending the function
+/// that converts the previous newest variant to it to [Versioned::MIGRATIONS].
+/// Migrations must never be removed or reordered.
+pub trait Versioned: Sized + 'static {
+  /// `MIGRATIONS[n]` converts a value of version `n + 1` to version `n + 2`.
+  /// It's only ever called with values of that version.
+  const MIGRATIONS: &'static [fn(Self) -> Self];
+
+  /// Version of this value, starting from 1
+  fn version(&self) -> usize;
+
+  fn latest_version() -> usize { Self::MIGRATIONS.len() + 1 }
+  fn is_latest(&self) -> bool { self.version() == Self::latest_version() }
+
+  /// Convert to the newest version
+  fn upgrade(self) -> Self {
+    let start = self.version();
+    Self::MIGRATIONS[start - 1..].iter().fold(self, |value, step| step(value))
+  }
+}
@@ -0,0 +1,2 @@
+ALTER TABLE board ADD COLUMN updated_at INT8 NOT NULL DEFAULT 0;
+UPDATE board SET updated_at = CAST(strftime('%s', 'now') AS INT8);
\ No newline at end of file
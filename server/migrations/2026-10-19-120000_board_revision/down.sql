@@ -0,0 +1 @@
+DROP TABLE board_revision;
\ No newline at end of file
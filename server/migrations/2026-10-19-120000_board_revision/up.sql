@@ -0,0 +1,10 @@
+CREATE TABLE board_revision (
+  board_id INT8 NOT NULL,
+  version INT4 NOT NULL,
+  layout TEXT NOT NULL,
+  author_id INT8,
+  created_at INT8 NOT NULL,
+  PRIMARY KEY (board_id, version)
+);
+INSERT INTO board_revision (board_id, version, layout, author_id, created_at)
+  SELECT id, version, layout, NULL, updated_at FROM board;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE board_member;
\ No newline at end of file
@@ -0,0 +1,10 @@
+CREATE TABLE board_member (
+  board_id INT8 NOT NULL,
+  user_id INT8 NOT NULL,
+  role TEXT NOT NULL,
+  accepted BOOL NOT NULL DEFAULT FALSE,
+  invited_by INT8,
+  created_at INT8 NOT NULL,
+  PRIMARY KEY (board_id, user_id)
+);
+CREATE INDEX board_member_user ON board_member (user_id);
\ No newline at end of file
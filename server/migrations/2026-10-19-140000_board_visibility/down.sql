@@ -0,0 +1 @@
+ALTER TABLE board DROP COLUMN visibility;
\ No newline at end of file
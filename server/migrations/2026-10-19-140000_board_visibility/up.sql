@@ -0,0 +1,2 @@
+-- boards used to be readable by anyone who knew their url
+ALTER TABLE board ADD COLUMN visibility TEXT NOT NULL DEFAULT 'unlisted';
\ No newline at end of file
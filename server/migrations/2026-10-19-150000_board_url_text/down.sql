@@ -0,0 +1,18 @@
+-- urls generated since can't be represented and are lost
+CREATE TABLE board_old (
+  id INT8 NOT NULL PRIMARY KEY,
+  url INT8 NOT NULL,
+  name TEXT NOT NULL,
+  version INT4 NOT NULL,
+  owner_id INT8 NOT NULL,
+  public_mut BOOL NOT NULL,
+  layout TEXT NOT NULL,
+  updated_at INT8 NOT NULL DEFAULT 0,
+  visibility TEXT NOT NULL DEFAULT 'unlisted'
+);
+INSERT INTO board_old (id, url, name, version, owner_id, public_mut, layout, updated_at, visibility)
+  SELECT id, CAST(url AS INT8), name, version, owner_id, public_mut, layout, updated_at, visibility
+  FROM board;
+DROP TABLE board;
+ALTER TABLE board_old RENAME TO board;
+CREATE INDEX idx_url_of_board ON board(url);
\ No newline at end of file
@@ -0,0 +1,18 @@
+-- SQLite can't change the type of a column, so the table is rebuilt.
+-- Existing numeric urls keep working in their decimal form.
+CREATE TABLE board_new (
+  id INT8 NOT NULL PRIMARY KEY,
+  url TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  version INT4 NOT NULL,
+  owner_id INT8 NOT NULL,
+  public_mut BOOL NOT NULL,
+  layout TEXT NOT NULL,
+  updated_at INT8 NOT NULL DEFAULT 0,
+  visibility TEXT NOT NULL DEFAULT 'unlisted'
+);
+INSERT INTO board_new (id, url, name, version, owner_id, public_mut, layout, updated_at, visibility)
+  SELECT id, CAST(url AS TEXT), name, version, owner_id, public_mut, layout, updated_at, visibility
+  FROM board;
+DROP TABLE board;
+ALTER TABLE board_new RENAME TO board;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE share_link;
\ No newline at end of file
@@ -0,0 +1,12 @@
+CREATE TABLE share_link (
+  token TEXT NOT NULL PRIMARY KEY,
+  board_id INT8 NOT NULL,
+  mode TEXT NOT NULL,
+  label TEXT,
+  expires_at INT8,
+  max_uses INT4,
+  uses INT4 NOT NULL DEFAULT 0,
+  created_by INT8 NOT NULL,
+  created_at INT8 NOT NULL
+);
+CREATE INDEX share_link_board ON share_link (board_id);
\ No newline at end of file
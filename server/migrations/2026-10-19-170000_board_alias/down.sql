@@ -0,0 +1 @@
+DROP TABLE board_alias;
\ No newline at end of file
@@ -0,0 +1,7 @@
+CREATE TABLE board_alias (
+  url TEXT NOT NULL PRIMARY KEY,
+  board_id INT8 NOT NULL,
+  expires_at INT8,
+  created_at INT8 NOT NULL
+);
+CREATE INDEX board_alias_board ON board_alias (board_id);
\ No newline at end of file
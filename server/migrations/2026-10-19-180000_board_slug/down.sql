@@ -0,0 +1,2 @@
+DROP TABLE board_slug;
+ALTER TABLE board DROP COLUMN slug;
\ No newline at end of file
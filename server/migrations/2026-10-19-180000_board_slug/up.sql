@@ -0,0 +1,10 @@
+ALTER TABLE board ADD COLUMN slug TEXT;
+-- every slug a board has had, so that renaming one doesn't break links to it
+CREATE TABLE board_slug (
+  owner_id INT8 NOT NULL,
+  slug TEXT NOT NULL,
+  board_id INT8 NOT NULL,
+  created_at INT8 NOT NULL,
+  PRIMARY KEY (owner_id, slug)
+);
+CREATE INDEX board_slug_board ON board_slug (board_id);
\ No newline at end of file
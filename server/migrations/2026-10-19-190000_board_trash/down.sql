@@ -0,0 +1,2 @@
+DROP INDEX board_deleted_at;
+ALTER TABLE board DROP COLUMN deleted_at;
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- boards in the trash are purged for good after a retention period
+ALTER TABLE board ADD COLUMN deleted_at INT8;
+CREATE INDEX board_deleted_at ON board (deleted_at);
\ No newline at end of file
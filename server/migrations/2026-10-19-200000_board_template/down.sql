@@ -0,0 +1 @@
+ALTER TABLE board DROP COLUMN is_template;
\ No newline at end of file
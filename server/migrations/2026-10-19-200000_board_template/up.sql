@@ -0,0 +1 @@
+ALTER TABLE board ADD COLUMN is_template BOOL NOT NULL DEFAULT FALSE;
\ No newline at end of file
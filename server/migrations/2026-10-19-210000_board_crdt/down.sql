@@ -0,0 +1 @@
+DROP TABLE board_crdt;
\ No newline at end of file
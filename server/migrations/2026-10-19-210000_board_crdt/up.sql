@@ -0,0 +1,6 @@
+-- boards edited collaboratively keep their layout as an Automerge document too
+CREATE TABLE board_crdt (
+  board_id INT8 PRIMARY KEY NOT NULL,
+  doc BLOB NOT NULL,
+  updated_at INT8 NOT NULL
+);
\ No newline at end of file
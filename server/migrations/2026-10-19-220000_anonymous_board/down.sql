@@ -0,0 +1,23 @@
+-- boards nobody has claimed are lost
+CREATE TABLE board_old (
+  id INT8 NOT NULL PRIMARY KEY,
+  url TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  version INT4 NOT NULL,
+  owner_id INT8 NOT NULL,
+  public_mut BOOL NOT NULL,
+  layout TEXT NOT NULL,
+  updated_at INT8 NOT NULL DEFAULT 0,
+  visibility TEXT NOT NULL DEFAULT 'unlisted',
+  slug TEXT,
+  deleted_at INT8,
+  is_template BOOL NOT NULL DEFAULT FALSE
+);
+INSERT INTO board_old (id, url, name, version, owner_id, public_mut, layout, updated_at,
+    visibility, slug, deleted_at, is_template)
+  SELECT id, url, name, version, owner_id, public_mut, layout, updated_at, visibility, slug,
+    deleted_at, is_template
+  FROM board WHERE owner_id IS NOT NULL;
+DROP TABLE board;
+ALTER TABLE board_old RENAME TO board;
+CREATE INDEX board_deleted_at ON board (deleted_at);
\ No newline at end of file
@@ -0,0 +1,26 @@
+-- boards created without an account have no owner, only an edit key, and
+-- SQLite can't drop the NOT NULL of owner_id, so the table is rebuilt
+CREATE TABLE board_new (
+  id INT8 NOT NULL PRIMARY KEY,
+  url TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  version INT4 NOT NULL,
+  owner_id INT8,
+  public_mut BOOL NOT NULL,
+  layout TEXT NOT NULL,
+  updated_at INT8 NOT NULL DEFAULT 0,
+  visibility TEXT NOT NULL DEFAULT 'unlisted',
+  slug TEXT,
+  deleted_at INT8,
+  is_template BOOL NOT NULL DEFAULT FALSE,
+  -- digest of the secret that lets anyone holding it edit the board
+  edit_key TEXT
+);
+INSERT INTO board_new (id, url, name, version, owner_id, public_mut, layout, updated_at,
+    visibility, slug, deleted_at, is_template)
+  SELECT id, url, name, version, owner_id, public_mut, layout, updated_at, visibility, slug,
+    deleted_at, is_template
+  FROM board;
+DROP TABLE board;
+ALTER TABLE board_new RENAME TO board;
+CREATE INDEX board_deleted_at ON board (deleted_at);
\ No newline at end of file
@@ -0,0 +1,2 @@
+DROP TABLE notification;
+DROP TABLE board_transfer;
\ No newline at end of file
@@ -0,0 +1,19 @@
+-- a board has at most one pending transfer
+CREATE TABLE board_transfer (
+  board_id INT8 NOT NULL PRIMARY KEY,
+  from_id INT8 NOT NULL,
+  to_id INT8 NOT NULL,
+  created_at INT8 NOT NULL
+);
+CREATE INDEX board_transfer_to ON board_transfer (to_id);
+CREATE TABLE notification (
+  id INT8 NOT NULL PRIMARY KEY,
+  user_id INT8 NOT NULL,
+  kind TEXT NOT NULL,
+  board_id INT8 NOT NULL,
+  -- the other user involved, if any
+  other_id INT8,
+  read BOOL NOT NULL DEFAULT FALSE,
+  created_at INT8 NOT NULL
+);
+CREATE INDEX notification_user ON notification (user_id, id);
\ No newline at end of file
@@ -0,0 +1,5 @@
+ALTER TABLE board DROP COLUMN updated_by;
+ALTER TABLE board DROP COLUMN created_at;
+ALTER TABLE board DROP COLUMN color;
+ALTER TABLE board DROP COLUMN icon;
+ALTER TABLE board DROP COLUMN description;
\ No newline at end of file
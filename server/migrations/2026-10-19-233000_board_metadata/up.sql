@@ -0,0 +1,11 @@
+-- shown alongside boards, and used to sort listings
+ALTER TABLE board ADD COLUMN description TEXT;
+ALTER TABLE board ADD COLUMN icon TEXT;
+ALTER TABLE board ADD COLUMN color TEXT;
+ALTER TABLE board ADD COLUMN created_at INT8 NOT NULL DEFAULT 0;
+ALTER TABLE board ADD COLUMN updated_by INT8;
+-- the oldest revision left is the best guess for boards that predate this
+UPDATE board SET created_at = COALESCE(
+  (SELECT MIN(created_at) FROM board_revision WHERE board_id = board.id), updated_at);
+UPDATE board SET updated_by = (SELECT author_id FROM board_revision
+  WHERE board_id = board.id ORDER BY version DESC LIMIT 1);
\ No newline at end of file
@@ -0,0 +1,158 @@
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use std::{env, fmt};
+
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::{HeaderValue, LOCATION};
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, web, HttpResponse, Responder, ResponseError};
+use common::{epoch_secs, AliasDetails};
+use diesel::prelude::*;
+
+use crate::auth::{AuthdUser, Visitor};
+use crate::boards::{check_access, find_board, Access, BoardNotFound};
+use crate::db::{self, BoardAlias, DbPool};
+use crate::schema::{board, board_alias};
+
+pub fn cfg_aliases(cfg: &mut web::ServiceConfig) {
+  cfg.service(list_aliases).service(delete_alias);
+}
+
+/// Seconds an alias keeps redirecting after a move, read from
+/// `BOARD_ALIAS_GRACE`. Defaults to 90 days, 0 means forever.
+fn grace() -> Option<i64> {
+  static GRACE: OnceLock<i64> = OnceLock::new();
+  let grace = *GRACE.get_or_init(|| match env::var("BOARD_ALIAS_GRACE") {
+    Ok(s) => s.parse().expect("BOARD_ALIAS_GRACE must be a number of seconds"),
+    Err(_) => 60 * 60 * 24 * 90,
+  });
+  (0 < grace).then_some(grace)
+}
+
+/// Keep redirecting `old_url` to the board for the grace period
+pub fn record(conn: &mut SqliteConnection, board: i64, old_url: String, now: i64) {
+  let alias = BoardAlias {
+    url: old_url,
+    board_id: board,
+    expires_at: grace().map(|g| now + g),
+    created_at: now,
+  };
+  diesel::replace_into(board_alias::table).values(alias).execute(conn).unwrap();
+}
+
+/// Current url of the board `alias` redirects to, if it's still in effect
+pub fn resolve(conn: &mut SqliteConnection, alias: &str) -> Option<String> {
+  let now = epoch_secs(SystemTime::now()) as i64;
+  (board_alias::table.inner_join(board::table.on(board_alias::board_id.eq(board::id))))
+    .filter(board_alias::url.eq(alias))
+    .filter(board_alias::expires_at.is_null().or(board_alias::expires_at.gt(now)))
+    .select(board::url)
+    .first(conn)
+    .optional()
+    .unwrap()
+}
+
+/// Delete every alias of a board
+pub fn purge(conn: &mut SqliteConnection, board: i64) {
+  use crate::schema::board_alias::dsl::*;
+  diesel::delete(board_alias.filter(board_id.eq(board))).execute(conn).unwrap();
+}
+
+#[derive(Clone, Debug)]
+pub enum AliasError {
+  Board(BoardNotFound),
+  NotFound,
+}
+impl From<BoardNotFound> for AliasError {
+  fn from(value: BoardNotFound) -> Self { Self::Board(value) }
+}
+impl fmt::Display for AliasError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Board(e) => write!(f, "{e}"),
+      Self::NotFound => write!(f, "This board has no such alias"),
+    }
+  }
+}
+impl ResponseError for AliasError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Board(e) => e.status_code(),
+      Self::NotFound => StatusCode::NOT_FOUND,
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Self::Board(e) => e.error_response(),
+      Self::NotFound => HttpResponse::NotFound().body(self.to_string()),
+    }
+  }
+}
+
+/// Attached to the redirect a request for an alias is answered with, so that
+/// [add_location] can point it to the same resource under the current url
+#[derive(Clone, Debug)]
+pub struct BoardMoved {
+  pub from: String,
+  pub to: String,
+}
+
+/// Middleware step that completes redirects of moved boards with a `Location`
+/// header, since the error that produces them doesn't know the request path
+pub fn add_location<B>(mut res: ServiceResponse<B>) -> ServiceResponse<B> {
+  let Some(moved) = res.response().extensions().get::<BoardMoved>().cloned() else {
+    return res;
+  };
+  let path = (res.request().path().split('/'))
+    .map(|seg| if seg == moved.from { &moved.to[..] } else { seg })
+    .collect::<Vec<_>>()
+    .join("/");
+  let location = match res.request().query_string() {
+    "" => path,
+    query => format!("{path}?{query}"),
+  };
+  res.response_mut().headers_mut().insert(LOCATION, HeaderValue::from_str(&location).unwrap());
+  res
+}
+
+#[get("/boards/{id}/aliases")]
+async fn list_aliases(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let aliases = db::read(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    check_access(conn, &current, &Visitor::user(ses_u.id), Access::Own)?;
+    let aliases = (board_alias::table.filter(board_alias::board_id.eq(current.id)))
+      .order(board_alias::created_at.desc())
+      .select(BoardAlias::as_select())
+      .load(conn)
+      .unwrap();
+    Ok::<_, BoardNotFound>(aliases)
+  })
+  .await?;
+  let aliases = (aliases.into_iter())
+    .map(|a| AliasDetails { url: a.url.into(), expires_at: a.expires_at, created_at: a.created_at })
+    .collect::<Vec<_>>();
+  Ok(HttpResponse::Ok().json(aliases))
+}
+
+#[delete("/boards/{id}/aliases/{alias}")]
+async fn delete_alias(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  path: web::Path<(String, String)>,
+) -> actix_web::Result<impl Responder> {
+  let (target, alias) = path.into_inner();
+  db::write(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    check_access(conn, &current, &Visitor::user(ses_u.id), Access::Own)?;
+    let alias = board_alias::table.find(&alias).filter(board_alias::board_id.eq(current.id));
+    let count = diesel::delete(alias).execute(conn).unwrap();
+    (0 < count).then_some(()).ok_or(AliasError::NotFound)
+  })
+  .await?;
+  Ok(HttpResponse::NoContent().finish())
+}
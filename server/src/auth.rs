@@ -1,19 +1,22 @@
 use std::collections::HashMap;
+use std::convert::Infallible;
 use std::fmt;
 use std::future::{ready, Ready};
 use std::time::{Duration, SystemTime};
 
 use actix_web::http::StatusCode;
 use actix_web::{post, web, FromRequest, HttpResponse, Responder, ResponseError};
-use common::{clone, epoch_secs, from_epoch_secs, ChangePassForm, TokenPair, UserDataForm};
+use common::{epoch_secs, from_epoch_secs, ChangePassForm, TokenPair, UserDataForm};
 use diesel::prelude::*;
-use diesel::result::DatabaseErrorKind;
 use diesel::{RunQueryDsl, SqliteConnection};
 use itertools::{partition, Itertools};
 
 use crate::bearer_token::{make_token, BearerToken, TokenError};
-use crate::db::{DbPool, Session, User};
+use crate::db::{self, DbPool, Session, User};
+use crate::edit_keys::EDIT_KEY;
+use crate::ids;
 use crate::schema::{session, user};
+use crate::shares::ShareGrant;
 
 pub fn cfg_auth(cfg: &mut web::ServiceConfig) {
   cfg.service(register).service(login).service(refresh).service(change_pass);
@@ -50,19 +53,55 @@ impl FromRequest for AuthdUser {
     payload: &mut actix_web::dev::Payload,
   ) -> Self::Future {
     ready((|| {
-      let mut token =
-        BearerToken::from_request(req, payload).into_inner().map_err(AuthError::Token)?;
-      if !token.claims.get("ty").is_some_and(|s| *s == "access") {
-        return Err(AuthError::NotAccess);
-      }
-      Ok(AuthdUser {
-        id: token.claims.remove("user_id").unwrap().parse().unwrap(),
-        name: token.claims.remove("name").unwrap(),
-        claims: token.claims,
-      })
+      let token = BearerToken::from_request(req, payload).into_inner().map_err(AuthError::Token)?;
+      AuthdUser::from_token(token)
     })())
   }
 }
+impl AuthdUser {
+  /// For tokens that don't come from the `Authorization` header
+  pub fn from_token(mut token: BearerToken) -> Result<Self, AuthError> {
+    if !token.claims.get("ty").is_some_and(|s| *s == "access") {
+      return Err(AuthError::NotAccess);
+    }
+    Ok(AuthdUser {
+      id: token.claims.remove("user_id").unwrap().parse().unwrap(),
+      name: token.claims.remove("name").unwrap(),
+      claims: token.claims,
+    })
+  }
+}
+
+/// Whoever makes a request to a board: a user, the holder of a share link,
+/// both or neither. Malformed or expired credentials are ignored, so they only
+/// result in less access.
+#[derive(Clone, Debug, Default)]
+pub struct Visitor {
+  pub user_id: Option<i64>,
+  pub grant: Option<ShareGrant>,
+  /// The edit key of a board, as sent in [EDIT_KEY]
+  pub edit_key: Option<String>,
+}
+impl Visitor {
+  pub fn user(id: i64) -> Self { Self { user_id: Some(id), ..Self::default() } }
+}
+impl FromRequest for Visitor {
+  type Error = Infallible;
+  type Future = Ready<Result<Self, Self::Error>>;
+  fn from_request(
+    req: &actix_web::HttpRequest,
+    payload: &mut actix_web::dev::Payload,
+  ) -> Self::Future {
+    let user = AuthdUser::from_request(req, payload).into_inner().ok();
+    let grant = ShareGrant::from_request(req, payload).into_inner().ok();
+    let edit_key = req.headers().get(EDIT_KEY).and_then(|key| key.to_str().ok());
+    ready(Ok(Visitor {
+      user_id: user.map(|u| u.id),
+      grant,
+      edit_key: edit_key.map(str::to_string),
+    }))
+  }
+}
 
 fn generate_token_pair(
   user_id: String,
@@ -126,23 +165,22 @@ pub async fn register(
   pool: web::Data<DbPool>,
   form: web::Json<UserDataForm>,
 ) -> actix_web::Result<impl Responder> {
-  let user = User {
-    id: rand::random::<i64>().abs(),
-    name: form.name.clone(),
-    pass_hash: pwhash::bcrypt::hash(&form.pass).unwrap(),
-  };
-  web::block(move || {
-    let mut conn = pool.get().unwrap();
-    match diesel::insert_into(user::table).values(&user).execute(&mut conn) {
-      Ok(_) => Ok(start_session(&mut conn, &user).1),
-      Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) =>
-        Err(RegisterError::NameTaken),
-      Err(e) => panic!("Unexpected database error {e}"),
+  let pass_hash = pwhash::bcrypt::hash(&form.pass).unwrap();
+  let tpair = db::write(&pool, move |conn| {
+    // checked first so that a conflict on the name isn't mistaken for one on the id
+    let taken = (user::table.filter(user::name.eq(&form.name))).count().get_result::<i64>(conn);
+    if 0 < taken.unwrap() {
+      return Err(RegisterError::NameTaken);
     }
+    let user = ids::insert_unique(|| {
+      let user = User { id: ids::row_id(), name: form.name.clone(), pass_hash: pass_hash.clone() };
+      diesel::insert_into(user::table).values(&user).execute(conn)?;
+      Ok(user)
+    });
+    Ok(start_session(conn, &user).1)
   })
-  .await?
-  .map_err(actix_web::Error::from)
-  .map(|tpair| HttpResponse::Ok().json(tpair))
+  .await?;
+  Ok(HttpResponse::Ok().json(tpair))
 }
 
 #[derive(Debug)]
@@ -167,41 +205,59 @@ impl ResponseError for LoginError {
   }
 }
 
-async fn login_logic(
-  pool: web::Data<DbPool>,
+/// Check the credentials and open a new session, dropping the user's expired
+/// sessions. This needs a connection of its own; the password is verified
+/// outside the transaction so that concurrent logins don't queue behind bcrypt
+/// on the write lock.
+fn login_logic(
+  conn: &mut SqliteConnection,
   form: UserDataForm,
-) -> actix_web::Result<(User, Session, TokenPair)> {
-  let user @ User { id: userid, .. } = web::block(clone!(pool, form; move || {
+) -> Result<(User, Session, TokenPair), LoginError> {
+  let user = verify_pass(conn, form)?;
+  let (ses, tpair) = db::atomic(conn, |conn| open_session(conn, &user))?;
+  Ok((user, ses, tpair))
+}
+
+/// The user named in the form, if the password matches
+fn verify_pass(conn: &mut SqliteConnection, form: UserDataForm) -> Result<User, LoginError> {
+  let user = {
     use crate::schema::user::dsl::*;
-    user.filter(name.eq(form.name))
-      .select(User::as_select())
-      .load(&mut pool.get().unwrap()).unwrap()
-      .into_iter().at_most_one().unwrap()
-  }))
-  .await?
-  .ok_or_else(|| actix_web::Error::from(LoginError::NoUser))?;
+    (user.filter(name.eq(form.name)).select(User::as_select()).load(conn).unwrap())
+      .into_iter()
+      .at_most_one()
+      .unwrap()
+      .ok_or(LoginError::NoUser)?
+  };
   if !pwhash::bcrypt::verify(&*form.pass, &*user.pass_hash) {
-    return Err(actix_web::Error::from(LoginError::BadPass));
+    return Err(LoginError::BadPass);
   }
-  let mut sessions = web::block(clone!(pool; move || {
-    use crate::schema::session::dsl::*;
-    session.filter(user_id.eq(userid))
-      .select(Session::as_select())
-      .load(&mut pool.get().unwrap()).unwrap()
-  }))
-  .await?;
+  Ok(user)
+}
+
+/// Open a session for a user whose password [verify_pass] accepted, dropping
+/// their expired sessions. Runs in the caller's transaction, which fails if the
+/// password was changed in the meantime.
+fn open_session(
+  conn: &mut SqliteConnection,
+  user: &User,
+) -> Result<(Session, TokenPair), LoginError> {
+  use crate::schema::session::dsl::*;
+  // the password may have been changed while we were hashing
+  let still_valid = {
+    use crate::schema::user::dsl as u;
+    let hash_q = u::user.find(user.id).select(u::pass_hash);
+    hash_q.first::<String>(conn).optional().unwrap().is_some_and(|h| h == user.pass_hash)
+  };
+  if !still_valid {
+    return Err(LoginError::BadPass);
+  }
+  let mut sessions =
+    session.filter(user_id.eq(user.id)).select(Session::as_select()).load(conn).unwrap();
   let now = epoch_secs(SystemTime::now()) as i64;
   let split_point = partition(&mut sessions, |s| now < s.refresh);
   let dropping = sessions.drain(split_point..).map(|s| s.token).collect_vec();
-  web::block(clone!(pool; move || {
-    use crate::schema::session::dsl::*;
-    diesel::delete(session.filter(token.eq_any(dropping)))
-      .execute(&mut pool.get().unwrap()).unwrap();
-  }))
-  .await?;
-  let (ses, tpair) =
-    web::block(clone!(user; move || start_session(&mut *pool.get().unwrap(), &user))).await?;
-  Ok((user, ses, tpair))
+  diesel::delete(session.filter(token.eq_any(dropping))).execute(conn).unwrap();
+  Ok(start_session(conn, user))
 }
 
 #[post("/auth/login")]
@@ -209,7 +265,7 @@ async fn login(
   pool: web::Data<DbPool>,
   form: web::Json<UserDataForm>,
 ) -> actix_web::Result<impl Responder> {
-  let (_, _, token_pair) = login_logic(pool, form.0).await?;
+  let (_, _, token_pair) = db::run(&pool, move |conn| login_logic(conn, form.0)).await?;
   Ok(HttpResponse::Ok().json(token_pair))
 }
 
@@ -248,18 +304,6 @@ async fn refresh(
   }
   let uid: i64 = bearer.claims.get("user_id").unwrap().parse().unwrap();
   let start_ts = bearer.claims.get("start").unwrap().parse::<u64>().unwrap();
-  let ses = web::block(clone!(pool; move || {
-    use crate::schema::session::dsl::*;
-    session.filter(user_id.eq(uid).and(start.eq(start_ts as i64)))
-      .select(Session::as_select())
-      .load(&mut pool.get().unwrap()).unwrap()
-      .into_iter().exactly_one().ok()
-  }))
-  .await?
-  .ok_or_else(|| actix_web::Error::from(RefreshError::ForceEnd))?;
-  if ses.token != bearer.token {
-    return Err(actix_web::Error::from(RefreshError::TokenReuse));
-  }
   let tpair = generate_token_pair(
     uid.to_string(),
     bearer.claims.get("name").unwrap().to_string(),
@@ -267,12 +311,20 @@ async fn refresh(
     from_epoch_secs(start_ts),
   );
   let refresh_token = tpair.refresh_token.clone();
-  web::block(move || {
+  db::write(&pool, move |conn| {
     use crate::schema::session::dsl::*;
-    diesel::update(session.find(ses.token))
-      .set(token.eq(refresh_token))
-      .execute(&mut pool.get().unwrap())
-      .unwrap();
+    let ses = (session.filter(user_id.eq(uid).and(start.eq(start_ts as i64))))
+      .select(Session::as_select())
+      .load(conn)
+      .unwrap()
+      .into_iter()
+      .exactly_one()
+      .map_err(|_| RefreshError::ForceEnd)?;
+    if ses.token != bearer.token {
+      return Err(RefreshError::TokenReuse);
+    }
+    diesel::update(session.find(ses.token)).set(token.eq(refresh_token)).execute(conn).unwrap();
+    Ok(())
   })
   .await?;
   Ok(HttpResponse::Ok().json(tpair))
@@ -283,16 +335,20 @@ async fn change_pass(
   pool: web::Data<DbPool>,
   form: web::Json<ChangePassForm>,
 ) -> actix_web::Result<impl Responder> {
-  let (User { id: uid, .. }, _, tpair) =
-    login_logic(pool.clone(), UserDataForm { name: form.name.clone(), pass: form.pass.clone() })
-      .await?;
   let new_hash = pwhash::bcrypt::hash(&form.new_pass).unwrap();
-  web::block(move || {
-    use crate::schema::user::dsl::*;
-    diesel::update(user.filter(id.eq(uid)))
-      .set(pass_hash.eq(new_hash))
-      .execute(&mut pool.get().unwrap())
-      .unwrap();
+  let login_form = UserDataForm { name: form.name.clone(), pass: form.pass.clone() };
+  let tpair = db::run(&pool, move |conn| {
+    let current = verify_pass(conn, login_form)?;
+    // checking that the old password still holds and replacing it happen
+    // together, so that of two concurrent changes only one succeeds
+    db::atomic(conn, |conn| {
+      let (_, tpair) = open_session(conn, &current)?;
+      use crate::schema::user::dsl::*;
+      (diesel::update(user.filter(id.eq(current.id))).set(pass_hash.eq(new_hash)))
+        .execute(conn)
+        .unwrap();
+      Ok::<_, LoginError>(tpair)
+    })
   })
   .await?;
   Ok(HttpResponse::Ok().json(tpair))
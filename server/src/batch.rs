@@ -0,0 +1,150 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::Arc;
+
+use actix_web::http::StatusCode;
+use actix_web::{post, web, HttpResponse, Responder, ResponseError};
+use common::{clone, BatchEntry, BatchRequest, BatchResponse, BatchResult};
+use itertools::Itertools;
+
+use crate::auth::Visitor;
+use crate::boards::{check_access, details, find_board, Access};
+use crate::cache::BoardCache;
+use crate::db::{self, Board, DbPool};
+use crate::migrate;
+
+pub fn cfg_batch(cfg: &mut web::ServiceConfig) { cfg.service(batch_boards); }
+
+pub const MAX_BATCH: usize = 200;
+
+#[derive(Clone, Debug)]
+pub struct TooManyBoards;
+impl fmt::Display for TooManyBoards {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "A batch can't ask for more than {MAX_BATCH} boards")
+  }
+}
+impl ResponseError for TooManyBoards {
+  fn status_code(&self) -> StatusCode { StatusCode::PAYLOAD_TOO_LARGE }
+}
+
+/// Outcome of looking up one url of the batch
+enum Lookup<B> {
+  Current(B),
+  /// New url of a moved board, and the board unless it was deleted since
+  Moved(String, Option<B>),
+  Gone {
+    trashed: bool,
+  },
+}
+impl<B> Lookup<B> {
+  fn board(&self) -> Option<&B> {
+    match self {
+      Self::Current(board) | Self::Moved(_, Some(board)) => Some(board),
+      Self::Moved(_, None) | Self::Gone { .. } => None,
+    }
+  }
+}
+
+/// Upgrade and cache a board read by the batch, like
+/// [crate::boards::load_board]
+async fn store(
+  pool: &DbPool,
+  cache: &BoardCache,
+  epoch: u64,
+  board: Board,
+) -> actix_web::Result<Arc<Board>> {
+  Ok(cache.insert(epoch, migrate::upgrade_board(pool, board).await?))
+}
+
+/// The boards of a layout in one round trip. Each one answers like a
+/// conditional read of its details and layout with the version the client
+/// holds, but the database is only visited twice whatever the size of the
+/// batch.
+#[post("/boards/batch")]
+async fn batch_boards(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  visitor: Visitor,
+  form: web::Json<BatchRequest>,
+) -> actix_web::Result<impl Responder> {
+  let wanted = form.into_inner().boards;
+  if MAX_BATCH < wanted.len() {
+    return Err(TooManyBoards.into());
+  }
+  let wanted = wanted.into_iter().unique_by(|entry| entry.url.clone()).collect::<Vec<_>>();
+  let mut found = HashMap::new();
+  let mut missing = Vec::new();
+  for BatchEntry { url, .. } in &wanted {
+    match cache.get(&url.0) {
+      Some(board) => drop(found.insert(url.0.clone(), Lookup::Current(board))),
+      None => missing.push(url.0.clone()),
+    }
+  }
+  if !missing.is_empty() {
+    let epoch = cache.epoch();
+    let read = db::read(
+      &pool,
+      clone!(visitor; move |conn| {
+        let read = (missing.into_iter())
+          .map(|target| {
+            let lookup = match find_board(conn, &target) {
+              Ok(board) => Lookup::Current(board),
+              Err(e) => match e.moved() {
+                Some(moved) => Lookup::Moved(moved.to.clone(), find_board(conn, &moved.to).ok()),
+                None => Lookup::Gone { trashed: e.seen_by(conn, &visitor).trashed() },
+              },
+            };
+            (target, lookup)
+          })
+          .collect::<Vec<_>>();
+        Ok::<_, Infallible>(read)
+      }),
+    )
+    .await?;
+    for (target, lookup) in read {
+      let lookup = match lookup {
+        Lookup::Current(board) => Lookup::Current(store(&pool, &cache, epoch, board).await?),
+        Lookup::Moved(to, Some(board)) =>
+          Lookup::Moved(to, Some(store(&pool, &cache, epoch, board).await?)),
+        Lookup::Moved(to, None) => Lookup::Moved(to, None),
+        Lookup::Gone { trashed } => Lookup::Gone { trashed },
+      };
+      found.insert(target, lookup);
+    }
+  }
+  let boards = found.values().filter_map(Lookup::board).cloned().collect::<Vec<_>>();
+  let visible = db::read(&pool, move |conn| {
+    let visible = (boards.iter())
+      .filter(|board| check_access(conn, board, &visitor, Access::View).is_ok())
+      .map(|board| board.id)
+      .collect::<HashSet<_>>();
+    Ok::<_, Infallible>(visible)
+  })
+  .await?;
+  // the layout is only sent if the client's copy is outdated
+  let changed = |board: &Board, held: Option<i32>| {
+    (visible.contains(&board.id) && held != Some(board.version)).then(
+      || match serde_json::from_str(&board.layout) {
+        Ok(layout) => BatchResult::Changed { details: details(board), layout },
+        Err(_) => BatchResult::Unreadable { url: board.url.clone().into() },
+      },
+    )
+  };
+  let mut results = Vec::new();
+  for BatchEntry { url, version } in wanted {
+    match found.remove(&url.0).unwrap() {
+      Lookup::Current(board) if !visible.contains(&board.id) =>
+        results.push(BatchResult::Gone { url, trashed: false }),
+      Lookup::Current(board) => results.extend(changed(&board, version)),
+      Lookup::Moved(to, board) => {
+        results.push(BatchResult::Moved { from: url, to: to.into() });
+        // the version held under the old url is still that of the same board
+        results.extend(board.and_then(|board| changed(&board, version)));
+      },
+      Lookup::Gone { trashed } => results.push(BatchResult::Gone { url, trashed }),
+    }
+  }
+  Ok(HttpResponse::Ok().json(BatchResponse { boards: results }))
+}
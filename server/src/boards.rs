@@ -1,14 +1,32 @@
-use std::fmt;
+use std::convert::Infallible;
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
+use std::{env, fmt};
 
-use actix_web::http::header::{EntityTag, IfMatch, IfNoneMatch};
+use actix_web::http::header::{
+  ETag, EntityTag, IfMatch, IfModifiedSince, IfNoneMatch, LastModified, CACHE_CONTROL, VARY,
+};
 use actix_web::http::StatusCode;
-use actix_web::{delete, get, post, web, HttpResponse, Responder, ResponseError};
-use common::{BoardDetails, BoardPatch, FreshBoard, NewBoardForm};
+use actix_web::{
+  delete, get, post, web, HttpResponse, HttpResponseBuilder, Responder, ResponseError,
+};
+use common::layout::{parse_board_layout, BoardLayout, BoardLayoutV2, FieldError};
+use common::merge::merge;
+use common::versioned::Versioned;
+use common::{
+  clone, epoch_secs, from_epoch_secs, BoardConflict, BoardDetails, BoardPatch, BoardRole, ForkForm,
+  FreshBoard, NewBoardForm, Visibility,
+};
 use diesel::prelude::*;
 use itertools::Itertools;
+use serde::Deserialize;
 
-use crate::auth::AuthdUser;
-use crate::db::{Board, DbPool};
+use crate::aliases::{self, BoardMoved};
+use crate::auth::{AuthdUser, Visitor};
+use crate::cache::BoardCache;
+use crate::db::{self, Board, DbPool};
+use crate::events::Hub;
+use crate::{crdt, edit_keys, ids, members, migrate, revisions, schema, shares};
 
 pub fn cfg_boards(cfg: &mut web::ServiceConfig) {
   cfg
@@ -16,68 +34,273 @@ pub fn cfg_boards(cfg: &mut web::ServiceConfig) {
     .service(manage_board)
     .service(edit_board)
     .service(new_board)
+    .service(fork_board)
     .service(move_board)
     .service(get_board_layout)
     .service(get_board);
 }
 
+/// Also returned when the user lacks permission, so that private boards can't
+/// be told apart from missing ones. When the url is an alias of a moved board
+/// it becomes a permanent redirect instead, and boards in the trash are gone
+/// to those who could view them, see [BoardNotFound::seen_by].
 #[derive(Clone, Debug)]
 pub struct BoardNotFound {
-  must_own: bool,
+  denied: bool,
+  moved: Option<BoardMoved>,
+  trashed: bool,
+  /// A board in the trash, not yet known to be viewable by the visitor
+  in_trash: Option<Box<Board>>,
+}
+impl BoardNotFound {
+  /// The answer to a visitor who lacks access, indistinguishable from a board
+  /// that doesn't exist
+  pub fn denied() -> Self { Self { denied: true, moved: None, trashed: false, in_trash: None } }
+  pub fn moved(&self) -> Option<&BoardMoved> { self.moved.as_ref() }
+  pub fn trashed(&self) -> bool { self.trashed }
+
+  /// Tell a board in the trash apart from a missing one, if the visitor could
+  /// view it
+  pub fn seen_by(self, conn: &mut SqliteConnection, visitor: &Visitor) -> Self {
+    match self.in_trash {
+      Some(board) if check_access(conn, &board, visitor, Access::View).is_ok() =>
+        Self { trashed: true, in_trash: None, ..self },
+      _ => self,
+    }
+  }
 }
 impl fmt::Display for BoardNotFound {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    match self.must_own {
-      true => write!(f, "Board moved, deleted, or not owned by this user"),
-      false => write!(f, "Board moved or deleted"),
+    match (&self.moved, self.trashed, self.denied) {
+      (Some(moved), ..) => write!(f, "Board moved to {}", moved.to),
+      (None, true, _) => write!(f, "Board deleted, only its owner can restore it"),
+      (None, false, true) => write!(f, "Board moved, deleted, or not accessible to this user"),
+      (None, false, false) => write!(f, "Board moved or deleted"),
     }
   }
 }
 impl ResponseError for BoardNotFound {
-  fn status_code(&self) -> StatusCode { StatusCode::NOT_FOUND }
+  fn status_code(&self) -> StatusCode {
+    match (&self.moved, self.trashed) {
+      (Some(_), _) => StatusCode::PERMANENT_REDIRECT,
+      (None, true) => StatusCode::GONE,
+      (None, false) => StatusCode::NOT_FOUND,
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    let mut rep = HttpResponse::build(self.status_code()).body(self.to_string());
+    if let Some(moved) = &self.moved {
+      // the Location header is filled in by aliases::add_location
+      rep.extensions_mut().insert(moved.clone());
+    }
+    rep
+  }
+}
+
+/// What a user may do with a board. Each level includes the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Access {
+  None,
+  View,
+  Edit,
+  Manage,
+  /// Only the owner may delete or move the board and transfer ownership
+  Own,
+}
+impl From<BoardRole> for Access {
+  fn from(value: BoardRole) -> Self {
+    match value {
+      BoardRole::Viewer => Self::View,
+      BoardRole::Editor => Self::Edit,
+      BoardRole::Manager => Self::Manage,
+    }
+  }
+}
+
+/// Unknown values are treated as private so that a bad row can't leak a board
+fn visibility(board: &Board) -> Visibility {
+  Visibility::parse(&board.visibility).unwrap_or(Visibility::Private)
+}
+
+/// Access that follows from the board alone. Anyone who can read a board that
+/// isn't private can view it, and edit it if it's publicly mutable.
+fn base_access(board: &Board, user_id: Option<i64>) -> Access {
+  match () {
+    _ if user_id.is_some() && user_id == board.owner_id => Access::Own,
+    _ if visibility(board) == Visibility::Private => Access::None,
+    _ if board.public_mut => Access::Edit,
+    _ => Access::View,
+  }
+}
+
+/// The permission check every board endpoint goes through. Memberships and
+/// share links are only looked up if the board itself doesn't grant enough.
+pub fn check_access(
+  conn: &mut SqliteConnection,
+  board: &Board,
+  visitor: &Visitor,
+  needed: Access,
+) -> Result<Access, BoardNotFound> {
+  let mut access = base_access(board, visitor.user_id);
+  if access < needed {
+    let role = visitor.user_id.and_then(|uid| members::role_of(conn, board.id, uid));
+    access = access.max(role.map_or(Access::None, Access::from));
+  }
+  if access < needed {
+    let shared = visitor.grant.as_ref().and_then(|g| shares::access_of(conn, board.id, g));
+    access = access.max(shared.unwrap_or(Access::None));
+  }
+  if access < needed {
+    let keyed = visitor.edit_key.as_ref().and_then(|key| edit_keys::access_of(board, key));
+    access = access.max(keyed.unwrap_or(Access::None));
+  }
+  match needed <= access {
+    true => Ok(access),
+    false => Err(BoardNotFound::denied()),
+  }
 }
 
+/// [check_access] for handlers that don't otherwise need a connection, such as
+/// cached reads
+pub async fn authorize(
+  pool: &DbPool,
+  board: &Board,
+  visitor: &Visitor,
+  needed: Access,
+) -> actix_web::Result<Access> {
+  let access = base_access(board, visitor.user_id);
+  if needed <= access {
+    return Ok(access);
+  }
+  let (board, visitor) = (board.clone(), visitor.clone());
+  db::read(pool, move |conn| check_access(conn, &board, &visitor, needed)).await
+}
+
+/// Move a board to its owner's trash, see [crate::trash]
 #[delete("/boards/{id}")]
 async fn del_board(
   pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
   ses_u: AuthdUser,
-  target_board: web::Path<i64>,
+  target_board: web::Path<String>,
 ) -> actix_web::Result<impl Responder> {
-  let count = web::block(move || {
-    use crate::schema::board::dsl::*;
-    diesel::delete(board.filter(owner_id.eq(ses_u.id).and(url.eq(*target_board))))
-      .execute(&mut pool.get().unwrap())
-      .unwrap()
-  })
+  let target = target_board.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  db::write(
+    &pool,
+    clone!(target; move |conn| {
+      use crate::schema::board::dsl::*;
+      let current = find_board(conn, &target)?;
+      check_access(conn, &current, &Visitor::user(ses_u.id), Access::Own)?;
+      diesel::update(board.find(current.id)).set(deleted_at.eq(now)).execute(conn).unwrap();
+      Ok::<_, BoardNotFound>(())
+    }),
+  )
   .await?;
-  (0 < count)
-    .then(|| HttpResponse::NoContent().finish())
-    .ok_or_else(|| BoardNotFound { must_own: true }.into())
+  cache.invalidate(&target);
+  hub.deleted(&target);
+  Ok(HttpResponse::NoContent().finish())
 }
 
 #[post("/boards/{id}")]
 async fn manage_board(
   pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
   ses_u: AuthdUser,
-  target_board: web::Path<i64>,
+  target_board: web::Path<String>,
   patch: web::Json<BoardPatch>,
+  ifmatch: Option<web::Header<IfMatch>>,
 ) -> actix_web::Result<impl Responder> {
-  let count = web::block(move || {
-    use crate::schema::board::dsl::*;
-    diesel::update(board.filter(url.eq(*target_board).and(owner_id.eq(ses_u.id))))
-      .set((
-        patch.name.as_ref().map(|n| name.eq(n.clone())),
-        patch.owner_id.map(|uid| owner_id.eq(uid)),
-        patch.public_mut.map(|pmut| public_mut.eq(pmut)),
-        version.eq(version + 1),
-      ))
-      .execute(&mut pool.get().unwrap())
-      .unwrap()
-  })
+  let tags = required_tags(ifmatch)?;
+  let errors = patch.validate();
+  if !errors.is_empty() {
+    return Err(InvalidPatch(errors).into());
+  }
+  let target = target_board.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let new_version = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      let current = find_board(conn, &target)?;
+      check_access(conn, &current, &Visitor::user(ses_u.id), Access::Manage)?;
+      check_version(&current, tags.as_deref(), false)?;
+      use crate::schema::board::dsl::*;
+      diesel::update(board.find(current.id))
+        .set((
+          patch.name.as_ref().map(|n| name.eq(n.clone())),
+          patch.public_mut.map(|pmut| public_mut.eq(pmut)),
+          patch.visibility.map(|vis| visibility.eq(vis.as_str())),
+          patch.is_template.map(|tmpl| is_template.eq(tmpl)),
+          patch.description.as_deref().map(|text| description.eq(unless_empty(text))),
+          patch.icon.as_deref().map(|text| icon.eq(unless_empty(text))),
+          patch.color.as_deref().map(|text| color.eq(unless_empty(text))),
+          version.eq(version + 1),
+          updated_at.eq(now),
+          updated_by.eq(ses_u.id),
+        ))
+        .execute(conn)
+        .unwrap();
+      Ok::<_, WriteError>(current.version + 1)
+    }),
+  )
   .await?;
-  (0 < count)
-    .then(|| HttpResponse::NoContent().finish())
-    .ok_or_else(|| BoardNotFound { must_own: true }.into())
+  cache.invalidate(&target);
+  hub.changed(&target, new_version);
+  Ok(HttpResponse::NoContent().insert_header(ETag(version_tag(new_version))).finish())
+}
+
+/// Patches clear optional settings with an empty string
+fn unless_empty(text: &str) -> Option<&str> { (!text.is_empty()).then_some(text) }
+
+/// Look a board up by its current url. Aliases left behind by moves resolve
+/// to a redirect error rather than the board, so that clients learn the new
+/// url. Boards in the trash are only found by [crate::trash], here they are
+/// missing until [BoardNotFound::seen_by] says otherwise.
+pub fn find_board(conn: &mut SqliteConnection, target: &str) -> Result<Board, BoardNotFound> {
+  use crate::schema::board::dsl::*;
+  let found = (board.filter(url.eq(target)))
+    .select(Board::as_select())
+    .load(conn)
+    .unwrap()
+    .into_iter()
+    .exactly_one();
+  match found {
+    Ok(current) if current.deleted_at.is_none() => Ok(current),
+    Ok(trashed) => Err(BoardNotFound {
+      denied: false,
+      moved: None,
+      trashed: false,
+      in_trash: Some(Box::new(trashed)),
+    }),
+    Err(_) => Err(BoardNotFound {
+      denied: false,
+      moved: aliases::resolve(conn, target).map(|to| BoardMoved { from: target.to_string(), to }),
+      trashed: false,
+      in_trash: None,
+    }),
+  }
+}
+
+/// Fetch a board by url through the cache, for a visitor whose access is
+/// checked afterwards
+pub async fn load_board(
+  pool: &DbPool,
+  cache: &BoardCache,
+  target: &str,
+  visitor: &Visitor,
+) -> actix_web::Result<Arc<Board>> {
+  if let Some(board) = cache.get(target) {
+    return Ok(board);
+  }
+  let epoch = cache.epoch();
+  let (target, visitor) = (target.to_string(), visitor.clone());
+  let board =
+    db::read(pool, move |conn| find_board(conn, &target).map_err(|e| e.seen_by(conn, &visitor)))
+      .await?;
+  let board = migrate::upgrade_board(pool, board).await?;
+  Ok(cache.insert(epoch, board))
 }
 
 #[derive(Clone, Debug)]
@@ -96,37 +319,359 @@ fn parse_etags<'a>(v: impl IntoIterator<Item = &'a EntityTag>) -> actix_web::Res
     .collect()
 }
 
+#[derive(Clone, Debug)]
+pub struct PreconditionRequired;
+impl fmt::Display for PreconditionRequired {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Modifying a board requires an If-Match header with the version you last saw")
+  }
+}
+impl ResponseError for PreconditionRequired {
+  fn status_code(&self) -> StatusCode { StatusCode::PRECONDITION_REQUIRED }
+}
+
+/// Read the versions from an `If-Match` header, [None] means any version
+pub fn required_tags(ifmatch: Option<web::Header<IfMatch>>) -> actix_web::Result<Option<Vec<i32>>> {
+  match ifmatch.as_deref() {
+    Some(IfMatch::Any) => Ok(None),
+    Some(IfMatch::Items(itv)) if !itv.is_empty() => Ok(Some(parse_etags(&itv[..])?)),
+    _ => Err(PreconditionRequired.into()),
+  }
+}
+
+pub fn version_tag(version: i32) -> EntityTag { EntityTag::new_strong(version.to_string()) }
+
+/// How a board was reached by a read. A slug may be released and come to name
+/// another board, so reads by slug are validated against the board's identity
+/// as well as its version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Route {
+  Url,
+  Slug,
+}
+impl Route {
+  fn tag(self, board: &Board) -> EntityTag {
+    match self {
+      Self::Url => version_tag(board.version),
+      Self::Slug => EntityTag::new_strong(format!("{}-{}", board.id, board.version)),
+    }
+  }
+}
+
+/// A conditional write lost the race against another write
+#[derive(Clone, Debug)]
+pub struct VersionConflict(BoardConflict);
+impl fmt::Display for VersionConflict {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "The board has been modified since, its current version is {}", self.0.version)
+  }
+}
+impl ResponseError for VersionConflict {
+  fn status_code(&self) -> StatusCode { StatusCode::PRECONDITION_FAILED }
+  fn error_response(&self) -> HttpResponse {
+    (HttpResponse::build(self.status_code()))
+      .insert_header(ETag(version_tag(self.0.version)))
+      .json(&self.0)
+  }
+}
+
+#[derive(Clone, Debug)]
+pub enum WriteError {
+  NotFound(BoardNotFound),
+  Conflict(VersionConflict),
+}
+impl From<BoardNotFound> for WriteError {
+  fn from(value: BoardNotFound) -> Self { Self::NotFound(value) }
+}
+impl fmt::Display for WriteError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::NotFound(e) => write!(f, "{e}"),
+      Self::Conflict(e) => write!(f, "{e}"),
+    }
+  }
+}
+impl ResponseError for WriteError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Self::NotFound(e) => e.status_code(),
+      Self::Conflict(e) => e.status_code(),
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Self::NotFound(e) => e.error_response(),
+      Self::Conflict(e) => e.error_response(),
+    }
+  }
+}
+
+/// Check the current version of a board against the ones the client accepts.
+/// The conflict optionally carries the current layout so that the client can
+/// merge without a second request.
+pub fn check_version(
+  board: &Board,
+  tags: Option<&[i32]>,
+  with_layout: bool,
+) -> Result<(), WriteError> {
+  match tags {
+    Some(tags) if !tags.contains(&board.version) =>
+      Err(WriteError::Conflict(VersionConflict(BoardConflict {
+        version: board.version,
+        layout: with_layout.then(|| board.layout.clone()),
+        conflicts: Vec::new(),
+      }))),
+    _ => Ok(()),
+  }
+}
+
+/// The submitted layout was rejected by [parse_board_layout]
+#[derive(Clone, Debug)]
+pub struct InvalidLayout(pub Vec<FieldError>);
+impl fmt::Display for InvalidLayout {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "The layout has {} problems", self.0.len())
+  }
+}
+impl ResponseError for InvalidLayout {
+  fn status_code(&self) -> StatusCode { StatusCode::UNPROCESSABLE_ENTITY }
+  fn error_response(&self) -> HttpResponse { HttpResponse::build(self.status_code()).json(&self.0) }
+}
+
+/// The submitted settings were rejected by [BoardPatch::validate]
+#[derive(Clone, Debug)]
+pub struct InvalidPatch(pub Vec<FieldError>);
+impl fmt::Display for InvalidPatch {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "The settings have {} problems", self.0.len())
+  }
+}
+impl ResponseError for InvalidPatch {
+  fn status_code(&self) -> StatusCode { StatusCode::UNPROCESSABLE_ENTITY }
+  fn error_response(&self) -> HttpResponse { HttpResponse::build(self.status_code()).json(&self.0) }
+}
+
+/// A write based on an older version changed things that were changed since
+#[derive(Clone, Debug)]
+pub struct MergeFailed(BoardConflict);
+impl fmt::Display for MergeFailed {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "The changes clash with {} changes made since", self.0.conflicts.len())
+  }
+}
+impl ResponseError for MergeFailed {
+  fn status_code(&self) -> StatusCode { StatusCode::CONFLICT }
+  fn error_response(&self) -> HttpResponse {
+    (HttpResponse::build(self.status_code()))
+      .insert_header(ETag(version_tag(self.0.version)))
+      .json(&self.0)
+  }
+}
+
+#[derive(Clone, Debug)]
+pub enum EditError {
+  Write(WriteError),
+  Unmerged(MergeFailed),
+  /// Merging two valid layouts can still exceed the limits
+  Invalid(InvalidLayout),
+}
+impl From<BoardNotFound> for EditError {
+  fn from(value: BoardNotFound) -> Self { Self::Write(value.into()) }
+}
+impl From<WriteError> for EditError {
+  fn from(value: WriteError) -> Self { Self::Write(value) }
+}
+impl fmt::Display for EditError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Write(e) => write!(f, "{e}"),
+      Self::Unmerged(e) => write!(f, "{e}"),
+      Self::Invalid(e) => write!(f, "{e}"),
+    }
+  }
+}
+impl ResponseError for EditError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Write(e) => e.status_code(),
+      Self::Unmerged(e) => e.status_code(),
+      Self::Invalid(e) => e.status_code(),
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Self::Write(e) => e.error_response(),
+      Self::Unmerged(e) => e.error_response(),
+      Self::Invalid(e) => e.error_response(),
+    }
+  }
+}
+
+/// Validate a layout and convert it to the canonical form it's stored in
+pub fn canonical_layout(text: &str) -> Result<String, InvalidLayout> {
+  Ok(serde_json::to_string(&parse_board_layout(text).map_err(InvalidLayout)?).unwrap())
+}
+
+/// Read a stored layout in its newest form, [None] if it isn't a layout the
+/// server understands
+pub fn parse_v2(text: &str) -> Option<BoardLayoutV2> {
+  match serde_json::from_str::<BoardLayout>(text).ok()?.upgrade() {
+    BoardLayout::V2(v2) => Some(v2),
+    BoardLayout::V1(_) => unreachable!("Layouts are upgraded to the newest version"),
+  }
+}
+
+/// Merge a layout based on an older version of a board into the current one.
+/// Without the base revision, which may have fallen out of the history, this
+/// is the usual version conflict.
+fn merge_layout(
+  conn: &mut SqliteConnection,
+  current: &Board,
+  base: i32,
+  ours: &str,
+) -> Result<String, EditError> {
+  let stale = || check_version(current, Some(&[base]), true).unwrap_err().into();
+  let base_rev = revisions::find_revision(conn, current, base).map_err(|_| stale())?;
+  let layouts = (parse_v2(&base_rev.layout), parse_v2(ours), parse_v2(&current.layout));
+  let (Some(base), Some(ours), Some(theirs)) = layouts else { return Err(stale()) };
+  match merge(&base, &ours, &theirs) {
+    Ok(merged) => {
+      let merged = BoardLayout::V2(merged);
+      let errors = merged.validate();
+      if !errors.is_empty() {
+        return Err(EditError::Invalid(InvalidLayout(errors)));
+      }
+      Ok(serde_json::to_string(&merged).unwrap())
+    },
+    Err(conflicts) => Err(EditError::Unmerged(MergeFailed(BoardConflict {
+      version: current.version,
+      layout: Some(current.layout.clone()),
+      conflicts,
+    }))),
+  }
+}
+
+#[derive(Deserialize)]
+struct EditQuery {
+  /// The version the layout was based on, in place of `If-Match`. Writes made
+  /// since are merged with it rather than failing the request.
+  base: Option<i32>,
+}
+
 #[post("/boards/{id}/layout")]
 async fn edit_board(
   pool: web::Data<DbPool>,
-  ses_u: Option<AuthdUser>,
-  target_board: web::Path<i64>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  visitor: Visitor,
+  (target_board, query): (web::Path<String>, web::Query<EditQuery>),
   new_layout: String,
-  ifmatch: web::Header<IfMatch>,
+  ifmatch: Option<web::Header<IfMatch>>,
 ) -> actix_web::Result<impl Responder> {
-  let tags = match &*ifmatch {
-    IfMatch::Items(itv) => Some(parse_etags(&itv[..])?),
-    IfMatch::Any => None,
+  let base = query.base;
+  let tags = match base {
+    Some(base) => Some(vec![base]),
+    None => required_tags(ifmatch)?,
   };
-  let count = web::block(move || {
-    use crate::schema::board::dsl::*;
-    let update = diesel::update(board.filter(url.eq(*target_board))).into_boxed();
-    let mut update = match ses_u {
-      Some(u) => update.filter(public_mut.eq(true).or(owner_id.eq(u.id))),
-      None => update.filter(public_mut.eq(true)),
-    };
-    if let Some(tags) = tags {
-      update = update.filter(version.eq_any(tags));
-    }
-    update
-      .set((layout.eq(new_layout), version.eq(version + 1)))
-      .execute(&mut pool.get().unwrap())
-      .unwrap()
-  })
+  let new_layout = canonical_layout(&new_layout)?;
+  let target = target_board.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let (new_version, merged) = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      let current = find_board(conn, &target)?;
+      let author = visitor.user_id;
+      check_access(conn, &current, &visitor, Access::Edit)?;
+      let merged = match base {
+        Some(base) if base != current.version =>
+          Some(merge_layout(conn, &current, base, &new_layout)?),
+        _ => {
+          check_version(&current, tags.as_deref(), true)?;
+          None
+        },
+      };
+      let layout = merged.clone().unwrap_or(new_layout);
+      Ok::<_, EditError>((commit_layout(conn, &current, layout, author, now), merged))
+    }),
+  )
   .await?;
-  (0 < count)
-    .then(|| HttpResponse::NoContent().finish())
-    .ok_or_else(|| BoardNotFound { must_own: true }.into())
+  cache.invalidate(&target);
+  hub.changed(&target, new_version);
+  let mut rep = match merged {
+    // the client needs the result, which differs from what it sent
+    Some(_) => HttpResponse::Ok(),
+    None => HttpResponse::NoContent(),
+  };
+  rep.insert_header(ETag(version_tag(new_version)));
+  Ok(match merged {
+    Some(merged) => rep.content_type("application/json").body(merged),
+    None => rep.finish(),
+  })
+}
+
+/// Replace the layout of a board, recording the new version in its history.
+/// Returns the new version.
+pub fn commit_layout(
+  conn: &mut SqliteConnection,
+  current: &Board,
+  new_layout: String,
+  author: Option<i64>,
+  now: i64,
+) -> i32 {
+  use crate::schema::board::dsl::*;
+  let new_version = current.version + 1;
+  revisions::record(conn, current.id, new_version, &new_layout, author, now);
+  crdt::write_through(conn, current.id, &new_layout, now);
+  diesel::update(board.find(current.id))
+    .set((
+      layout.eq(new_layout),
+      version.eq(new_version),
+      updated_at.eq(now),
+      updated_by.eq(author),
+    ))
+    .execute(conn)
+    .unwrap();
+  new_version
+}
+
+/// Create a board owned by `owner_id`, or by nobody, at a fresh url, starting
+/// its history
+pub fn insert_board(
+  conn: &mut SqliteConnection,
+  owner_id: Option<i64>,
+  name: String,
+  public_mut: bool,
+  visibility: Visibility,
+  layout: String,
+  now: i64,
+) -> FreshBoard {
+  let (id, url) = ids::insert_unique(|| {
+    let (id, url) = (ids::row_id(), ids::board_url());
+    let row = Board {
+      id,
+      url: url.clone(),
+      name: name.clone(),
+      public_mut,
+      layout: layout.clone(),
+      owner_id,
+      version: 0,
+      updated_at: now,
+      visibility: visibility.as_str().to_string(),
+      slug: None,
+      deleted_at: None,
+      is_template: false,
+      edit_key: None,
+      description: None,
+      icon: None,
+      color: None,
+      created_at: now,
+      updated_by: owner_id,
+    };
+    diesel::insert_into(schema::board::table).values(row).execute(conn)?;
+    Ok((id, url))
+  });
+  revisions::record(conn, id, 0, &layout, owner_id, now);
+  FreshBoard { id, url: url.into() }
 }
 
 #[post("/new_board")]
@@ -135,95 +680,296 @@ async fn new_board(
   ses_u: AuthdUser,
   form: web::Json<NewBoardForm>,
 ) -> actix_web::Result<impl Responder> {
-  let NewBoardForm { layout, name, public_mut } = form.clone();
-  let [id, url]: [i64; 2] = rand::random::<[u32; 2]>().map(i64::from);
-  let new_board = Board { id, name, url, public_mut, layout, owner_id: ses_u.id, version: 0 };
-  web::block(move || {
-    use crate::schema::board::dsl::*;
-    diesel::insert_into(board).values(new_board).execute(&mut pool.get().unwrap()).unwrap();
+  let NewBoardForm { layout, name, public_mut, visibility } = form.clone();
+  let layout = canonical_layout(&layout)?;
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let fresh = db::write(&pool, move |conn| {
+    Ok::<_, Infallible>(insert_board(
+      conn,
+      Some(ses_u.id),
+      name,
+      public_mut,
+      visibility,
+      layout,
+      now,
+    ))
   })
   .await?;
-  Ok(HttpResponse::Ok().json(FreshBoard { id, url }))
+  Ok(HttpResponse::Ok().json(fresh))
 }
 
-#[post("/boards/{id}/move")]
-async fn move_board(
+/// Copy a board the user can read into their account. Only the name, layout
+/// and visibility are copied, members, share links and history stay with the
+/// original.
+#[post("/boards/{id}/fork")]
+async fn fork_board(
   pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
   ses_u: AuthdUser,
-  target_board: web::Path<i64>,
+  visitor: Visitor,
+  target_board: web::Path<String>,
+  form: Option<web::Json<ForkForm>>,
 ) -> actix_web::Result<impl Responder> {
-  let new_url: i64 = rand::random::<u32>().into();
-  let count = web::block(move || {
-    use crate::schema::board::dsl::*;
-    diesel::update(board.filter(url.eq(*target_board).and(owner_id.eq(ses_u.id))))
-      .set(url.eq(new_url))
-      .execute(&mut pool.get().unwrap())
-      .unwrap()
+  let ForkForm { name, visibility: vis } = form.map(|f| f.into_inner()).unwrap_or_default();
+  let original = load_board(&pool, &cache, &target_board, &visitor).await?;
+  authorize(&pool, &original, &visitor, Access::View).await?;
+  let name = name.unwrap_or_else(|| original.name.clone());
+  let vis = vis.unwrap_or_else(|| visibility(&original));
+  let layout = original.layout.clone();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let fresh = db::write(&pool, move |conn| {
+    Ok::<_, Infallible>(insert_board(conn, Some(ses_u.id), name, false, vis, layout, now))
   })
   .await?;
-  (0 < count)
-    .then(|| HttpResponse::Ok().body(new_url.to_string()))
-    .ok_or_else(|| BoardNotFound { must_own: true }.into())
+  Ok(HttpResponse::Ok().json(fresh))
 }
 
-#[get("/boards/{id}")]
-async fn get_board(
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MoveQuery {
+  /// Keep redirecting the old url to the new one for a while
+  #[serde(default)]
+  keep_alias: bool,
+}
+
+#[post("/boards/{id}/move")]
+async fn move_board(
   pool: web::Data<DbPool>,
-  target_board: web::Path<i64>,
-  ifnmatch: web::Header<IfNoneMatch>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+  query: web::Query<MoveQuery>,
 ) -> actix_web::Result<impl Responder> {
-  let known = match &*ifnmatch {
-    IfNoneMatch::Items(itv) => parse_etags(&itv[..])?,
-    IfNoneMatch::Any => Vec::new(),
-  };
-  let board: Option<Board> = web::block(move || {
-    use crate::schema::board::dsl::*;
-    (board.filter(url.eq(*target_board)))
-      .select(Board::as_select())
-      .load(&mut pool.get().unwrap())
-      .unwrap()
-      .into_iter()
-      .exactly_one()
-      .ok()
-  })
+  let target = target_board.into_inner();
+  let keep_alias = query.keep_alias;
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let new_url = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      use crate::schema::board::dsl::*;
+      let current = find_board(conn, &target)?;
+      check_access(conn, &current, &Visitor::user(ses_u.id), Access::Own)?;
+      let new_url = ids::insert_unique(|| {
+        let new_url = ids::board_url();
+        diesel::update(board.find(current.id)).set(url.eq(&new_url)).execute(conn)?;
+        Ok(new_url)
+      });
+      if keep_alias {
+        aliases::record(conn, current.id, current.url, now);
+      }
+      Ok::<_, BoardNotFound>(new_url)
+    }),
+  )
   .await?;
-  let board = board.ok_or(BoardNotFound { must_own: false })?;
-  if known.iter().any(|v| board.version == *v) {
-    return Ok(HttpResponse::NotModified().finish());
+  cache.invalidate(&target);
+  // without an alias the move revokes the old url, and the new one is only
+  // for those the owner gives it to
+  match keep_alias {
+    true => hub.moved(&target, &new_url),
+    false => hub.deleted(&target),
+  }
+  Ok(HttpResponse::Ok().body(new_url))
+}
+
+/// `Cache-Control` sent with board representations, read from
+/// `BOARD_CACHE_CONTROL`. The default lets browsers and proxies store boards
+/// but makes them revalidate every time, which is cheap thanks to the ETag.
+fn cache_control() -> &'static str {
+  static CACHE_CONTROL: OnceLock<String> = OnceLock::new();
+  CACHE_CONTROL.get_or_init(|| env::var("BOARD_CACHE_CONTROL").unwrap_or("no-cache".to_string()))
+}
+
+/// Start a response carrying the validators and caching policy of `board`.
+/// Private boards must not be stored by shared caches, since the answer
+/// depends on who is asking. Reads by slug get no `Last-Modified`, which can't
+/// tell boards apart.
+fn board_response(status: StatusCode, board: &Board, route: Route) -> HttpResponseBuilder {
+  let mut rep = HttpResponse::build(status);
+  rep.insert_header(ETag(route.tag(board)));
+  if route == Route::Url {
+    rep.insert_header(LastModified(from_epoch_secs(board.updated_at as u64).into()));
   }
-  Ok(HttpResponse::Ok().json(BoardDetails {
+  match visibility(board) {
+    Visibility::Private => rep.insert_header((CACHE_CONTROL, "private, no-cache")),
+    Visibility::Public | Visibility::Unlisted =>
+      rep.insert_header((CACHE_CONTROL, cache_control())),
+  };
+  rep.insert_header((VARY, "Accept-Encoding, Authorization, X-Share-Grant, X-Edit-Key"));
+  rep
+}
+
+/// Evaluate the conditional request headers against the current version of the
+/// board. As per RFC 9110, `If-Modified-Since` is only considered if there is
+/// no `If-None-Match`.
+fn not_modified(
+  board: &Board,
+  route: Route,
+  ifnmatch: Option<&IfNoneMatch>,
+  ifmodsince: Option<&IfModifiedSince>,
+) -> actix_web::Result<bool> {
+  Ok(match (route, ifnmatch, ifmodsince) {
+    (_, Some(IfNoneMatch::Any), _) => true,
+    (Route::Url, Some(IfNoneMatch::Items(itv)), _) if !itv.is_empty() =>
+      parse_etags(&itv[..])?.contains(&board.version),
+    (Route::Slug, Some(IfNoneMatch::Items(itv)), _) =>
+      itv.iter().any(|tag| tag.strong_eq(&route.tag(board))),
+    (Route::Url, _, Some(IfModifiedSince(since))) =>
+      board.updated_at as u64 <= epoch_secs((*since).into()),
+    (..) => false,
+  })
+}
+
+#[get("/boards/{id}")]
+async fn get_board(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  visitor: Visitor,
+  target_board: web::Path<String>,
+  ifnmatch: Option<web::Header<IfNoneMatch>>,
+  ifmodsince: Option<web::Header<IfModifiedSince>>,
+) -> actix_web::Result<HttpResponse> {
+  let board = load_board(&pool, &cache, &target_board, &visitor).await?;
+  let (ifnmatch, ifmodsince) = (ifnmatch.as_deref(), ifmodsince.as_deref());
+  details_response(&pool, &board, &visitor, Route::Url, ifnmatch, ifmodsince).await
+}
+
+#[get("/boards/{id}/layout")]
+async fn get_board_layout(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  visitor: Visitor,
+  target_board: web::Path<String>,
+  ifnmatch: Option<web::Header<IfNoneMatch>>,
+  ifmodsince: Option<web::Header<IfModifiedSince>>,
+) -> actix_web::Result<HttpResponse> {
+  let board = load_board(&pool, &cache, &target_board, &visitor).await?;
+  let (ifnmatch, ifmodsince) = (ifnmatch.as_deref(), ifmodsince.as_deref());
+  layout_response(&pool, &board, &visitor, Route::Url, ifnmatch, ifmodsince).await
+}
+
+pub fn details(board: &Board) -> BoardDetails {
+  BoardDetails {
     id: board.id,
-    name: board.name,
+    url: board.url.clone().into(),
+    slug: board.slug.clone(),
+    name: board.name.clone(),
     version: board.version,
     owner_id: board.owner_id,
     public_mut: board.public_mut,
-  }))
+    visibility: visibility(board),
+    is_template: board.is_template,
+    description: board.description.clone(),
+    icon: board.icon.clone(),
+    color: board.color.clone(),
+    created_at: board.created_at,
+    updated_at: board.updated_at,
+    updated_by: board.updated_by,
+  }
 }
 
-#[get("/boards/{id}/layout")]
-async fn get_board_layout(
-  pool: web::Data<DbPool>,
-  target_board: web::Path<i64>,
-  ifnmatch: web::Header<IfNoneMatch>,
-) -> actix_web::Result<impl Responder> {
-  let known = match &*ifnmatch {
-    IfNoneMatch::Items(itv) => parse_etags(&itv[..])?,
-    IfNoneMatch::Any => Vec::new(),
-  };
-  let board: Option<Board> = web::block(move || {
-    use crate::schema::board::dsl::*;
-    (board.filter(url.eq(*target_board)))
-      .select(Board::as_select())
-      .load(&mut pool.get().unwrap())
-      .unwrap()
-      .into_iter()
-      .exactly_one()
-      .ok()
-  })
-  .await?;
-  let board = board.ok_or(BoardNotFound { must_own: false })?;
-  if known.iter().any(|v| board.version == *v) {
-    return Ok(HttpResponse::NotModified().finish());
+/// Answer a conditional read of the details of a board the visitor may view
+pub async fn details_response(
+  pool: &DbPool,
+  board: &Board,
+  visitor: &Visitor,
+  route: Route,
+  ifnmatch: Option<&IfNoneMatch>,
+  ifmodsince: Option<&IfModifiedSince>,
+) -> actix_web::Result<HttpResponse> {
+  authorize(pool, board, visitor, Access::View).await?;
+  if not_modified(board, route, ifnmatch, ifmodsince)? {
+    return Ok(board_response(StatusCode::NOT_MODIFIED, board, route).finish());
+  }
+  Ok(board_response(StatusCode::OK, board, route).json(details(board)))
+}
+
+/// Answer a conditional read of the layout of a board the visitor may view
+pub async fn layout_response(
+  pool: &DbPool,
+  board: &Board,
+  visitor: &Visitor,
+  route: Route,
+  ifnmatch: Option<&IfNoneMatch>,
+  ifmodsince: Option<&IfModifiedSince>,
+) -> actix_web::Result<HttpResponse> {
+  authorize(pool, board, visitor, Access::View).await?;
+  if not_modified(board, route, ifnmatch, ifmodsince)? {
+    return Ok(board_response(StatusCode::NOT_MODIFIED, board, route).finish());
+  }
+  Ok(board_response(StatusCode::OK, board, route).body(board.layout.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+  use std::num::NonZeroUsize;
+  use std::time::Duration;
+
+  use actix_web::http::header::{AUTHORIZATION, IF_MATCH};
+  use actix_web::{test, App};
+  use common::layout::{Column, Entry, Item, ItemKind};
+
+  use super::*;
+  use crate::bearer_token::make_token;
+
+  fn note(id: u64, text: &str) -> Entry {
+    Entry::Item(Item::new(id, ItemKind::Note { text: text.to_string() }))
+  }
+
+  fn layout(entries: Vec<Entry>) -> String {
+    let columns = vec![Column { title: "Notes".to_string(), entries }];
+    serde_json::to_string(&BoardLayout::V2(BoardLayoutV2 { columns })).unwrap()
+  }
+
+  #[actix_web::test]
+  async fn merges_onto_a_version_that_only_renamed_the_board() {
+    env::set_var("JWT_SECRET", "test");
+    let claims = HashMap::from([
+      ("ty".to_string(), "access".to_string()),
+      ("user_id".to_string(), "1".to_string()),
+      ("name".to_string(), "alice".to_string()),
+    ]);
+    let token = make_token(SystemTime::now(), Duration::from_secs(60), claims);
+    let auth = (AUTHORIZATION, format!("Bearer {token}"));
+    let pool = db::test_pool();
+    let original = layout(vec![note(1, "a")]);
+    let fresh = insert_board(
+      &mut pool.get().unwrap(),
+      Some(1),
+      "Home".to_string(),
+      false,
+      Visibility::Private,
+      original,
+      0,
+    );
+    let app = test::init_service(
+      App::new()
+        .app_data(web::Data::new(pool))
+        .app_data(web::Data::new(BoardCache::new(NonZeroUsize::new(8).unwrap())))
+        .app_data(web::Data::new(Hub::new()))
+        .configure(cfg_boards),
+    )
+    .await;
+    let board_uri = format!("/boards/{}", fresh.url.0);
+    let post = |uri: &str| test::TestRequest::post().uri(uri).insert_header(auth.clone());
+
+    // version 1 has no revision of its own
+    let rename = post(&board_uri)
+      .insert_header((IF_MATCH, version_tag(0).to_string()))
+      .set_json(serde_json::json!({ "name": "Renamed" }));
+    let rep = test::call_service(&app, rename.to_request()).await;
+    assert_eq!(rep.status(), StatusCode::NO_CONTENT);
+
+    let layout_uri = format!("{board_uri}/layout?base=1");
+    let theirs = post(&layout_uri).set_payload(layout(vec![note(1, "a"), note(2, "theirs")]));
+    let rep = test::call_service(&app, theirs.to_request()).await;
+    assert_eq!(rep.status(), StatusCode::NO_CONTENT);
+
+    let ours = post(&layout_uri).set_payload(layout(vec![note(1, "ours")]));
+    let rep = test::call_service(&app, ours.to_request()).await;
+    assert_eq!(rep.status(), StatusCode::OK);
+    let merged = String::from_utf8(test::read_body(rep).await.to_vec()).unwrap();
+    assert_eq!(merged, layout(vec![note(1, "ours"), note(2, "theirs")]));
   }
-  Ok(HttpResponse::Ok().body(board.layout))
 }
@@ -0,0 +1,62 @@
+use std::env;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+use lru::LruCache;
+
+use crate::db::Board;
+
+/// Bounded in-memory copy of recently read boards keyed by their public url, so
+/// that conditional reads of hot boards can be answered without touching
+/// SQLite.
+///
+/// Every write path must call [BoardCache::invalidate] after its transaction
+/// commits. Readers capture [BoardCache::epoch] before querying the database,
+/// and [BoardCache::insert] refuses their result if anything was invalidated in
+/// the meantime, because the row they read may predate that write.
+pub struct BoardCache {
+  inner: Mutex<Inner>,
+}
+
+struct Inner {
+  epoch: u64,
+  boards: LruCache<String, Arc<Board>>,
+}
+
+impl BoardCache {
+  pub fn new(capacity: NonZeroUsize) -> Self {
+    Self { inner: Mutex::new(Inner { epoch: 0, boards: LruCache::new(capacity) }) }
+  }
+
+  /// Size read from `BOARD_CACHE_SIZE`, defaulting to 1024 boards
+  pub fn from_env() -> Self {
+    let capacity = match env::var("BOARD_CACHE_SIZE") {
+      Ok(s) => s.parse().expect("BOARD_CACHE_SIZE must be a positive integer"),
+      Err(_) => NonZeroUsize::new(1024).unwrap(),
+    };
+    Self::new(capacity)
+  }
+
+  pub fn get(&self, url: &str) -> Option<Arc<Board>> {
+    self.inner.lock().unwrap().boards.get(url).cloned()
+  }
+
+  pub fn epoch(&self) -> u64 { self.inner.lock().unwrap().epoch }
+
+  /// Store a board read from the database after observing `epoch`. Returns the
+  /// board either way for convenience.
+  pub fn insert(&self, epoch: u64, board: Board) -> Arc<Board> {
+    let board = Arc::new(board);
+    let mut inner = self.inner.lock().unwrap();
+    if inner.epoch == epoch {
+      inner.boards.put(board.url.clone(), board.clone());
+    }
+    board
+  }
+
+  pub fn invalidate(&self, url: &str) {
+    let mut inner = self.inner.lock().unwrap();
+    inner.epoch += 1;
+    inner.boards.pop(url);
+  }
+}
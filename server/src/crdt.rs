@@ -0,0 +1,232 @@
+//! Collaborative editing of boards through Automerge documents. A board gets a
+//! document the first time a client asks for it, after which every write to
+//! the board goes through it, whether it comes from a sync or from the plain
+//! layout endpoints. The layout column stays the compacted snapshot that
+//! clients without a document read.
+
+use std::fmt;
+use std::time::SystemTime;
+
+use actix_web::http::header::{ETag, CACHE_CONTROL};
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, HttpResponse, Responder, ResponseError};
+use automerge::{AutoCommit, ChangeHash};
+use common::crdt::{hydrate, new_doc, reconcile};
+use common::layout::BoardLayout;
+use common::{clone, epoch_secs};
+use diesel::prelude::*;
+use serde::Deserialize;
+
+use crate::auth::Visitor;
+use crate::boards::{
+  check_access, commit_layout, find_board, parse_v2, version_tag, Access, BoardNotFound,
+  InvalidLayout,
+};
+use crate::cache::BoardCache;
+use crate::db::{self, Board, BoardCrdt, DbPool};
+use crate::events::Hub;
+
+pub fn cfg_crdt(cfg: &mut web::ServiceConfig) { cfg.service(get_doc).service(sync_doc); }
+
+#[derive(Clone, Debug)]
+pub enum CrdtError {
+  Board(BoardNotFound),
+  /// The layout isn't in a format the server understands, so it has no document
+  Opaque,
+  /// The changes or heads sent by the client couldn't be read
+  Malformed(String),
+  /// Concurrent changes merged into something that isn't a layout
+  Unmergeable(String),
+  Invalid(InvalidLayout),
+}
+impl From<BoardNotFound> for CrdtError {
+  fn from(value: BoardNotFound) -> Self { Self::Board(value) }
+}
+impl From<InvalidLayout> for CrdtError {
+  fn from(value: InvalidLayout) -> Self { Self::Invalid(value) }
+}
+impl fmt::Display for CrdtError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Board(e) => write!(f, "{e}"),
+      Self::Opaque => write!(f, "This board's layout isn't in a format that can be edited jointly"),
+      Self::Malformed(e) => write!(f, "Couldn't read the changes: {e}"),
+      Self::Unmergeable(e) => write!(f, "The changes don't merge into a layout: {e}"),
+      Self::Invalid(e) => write!(f, "{e}"),
+    }
+  }
+}
+impl ResponseError for CrdtError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Board(e) => e.status_code(),
+      Self::Opaque | Self::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+      Self::Malformed(_) => StatusCode::BAD_REQUEST,
+      Self::Unmergeable(_) => StatusCode::CONFLICT,
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Self::Board(e) => e.error_response(),
+      Self::Invalid(e) => e.error_response(),
+      _ => HttpResponse::build(self.status_code()).body(self.to_string()),
+    }
+  }
+}
+
+fn load(conn: &mut SqliteConnection, board: i64) -> Option<AutoCommit> {
+  use crate::schema::board_crdt::dsl::*;
+  let saved = board_crdt.find(board).select(doc).first::<Vec<u8>>(conn).optional().unwrap()?;
+  Some(AutoCommit::load(&saved).expect("Stored documents are valid"))
+}
+
+fn store(conn: &mut SqliteConnection, board: i64, document: &mut AutoCommit, now: i64) {
+  use crate::schema::board_crdt::dsl::*;
+  let row = BoardCrdt { board_id: board, doc: document.save(), updated_at: now };
+  diesel::replace_into(board_crdt).values(row).execute(conn).unwrap();
+}
+
+/// The document of a board, created from its layout if it has none yet
+fn load_or_create(
+  conn: &mut SqliteConnection,
+  board: &Board,
+  now: i64,
+) -> Result<AutoCommit, CrdtError> {
+  if let Some(document) = load(conn, board.id) {
+    return Ok(document);
+  }
+  let layout = parse_v2(&board.layout).ok_or(CrdtError::Opaque)?;
+  let mut document = new_doc(&layout).unwrap();
+  store(conn, board.id, &mut document, now);
+  Ok(document)
+}
+
+/// Bring the document of a board in line with a layout that was written
+/// without it. Called by [commit_layout], so that every write reaches peers
+/// that edit through the document. A layout the document can't hold drops it,
+/// and clients fall back to plain edits.
+pub fn write_through(conn: &mut SqliteConnection, board: i64, layout: &str, now: i64) {
+  let Some(mut document) = load(conn, board) else { return };
+  match parse_v2(layout) {
+    Some(layout) => {
+      reconcile(&mut document, &layout).unwrap();
+      store(conn, board, &mut document, now);
+    },
+    None => purge(conn, board),
+  }
+}
+
+/// Delete the document of a board
+pub fn purge(conn: &mut SqliteConnection, board: i64) {
+  use crate::schema::board_crdt::dsl::*;
+  diesel::delete(board_crdt.find(board)).execute(conn).unwrap();
+}
+
+fn doc_response(version: i32, bytes: Vec<u8>) -> HttpResponse {
+  HttpResponse::Ok()
+    .insert_header(ETag(version_tag(version)))
+    .insert_header((CACHE_CONTROL, "no-store"))
+    .content_type("application/octet-stream")
+    .body(bytes)
+}
+
+/// The whole document of a board, in Automerge's binary format. The `ETag` is
+/// the version of the board it matches.
+#[get("/boards/{id}/crdt")]
+async fn get_doc(
+  pool: web::Data<DbPool>,
+  visitor: Visitor,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let stored = db::read(
+    &pool,
+    clone!(target, visitor; move |conn| {
+      let current = find_board(conn, &target)?;
+      check_access(conn, &current, &visitor, Access::View)?;
+      let saved = load(conn, current.id).map(|mut document| document.save());
+      Ok::<_, CrdtError>(saved.map(|bytes| (current.version, bytes)))
+    }),
+  )
+  .await?;
+  let (version, bytes) = match stored {
+    Some(stored) => stored,
+    // only creating the document takes the write lock
+    None => {
+      let now = epoch_secs(SystemTime::now()) as i64;
+      db::write(&pool, move |conn| {
+        let current = find_board(conn, &target)?;
+        check_access(conn, &current, &visitor, Access::View)?;
+        let mut document = load_or_create(conn, &current, now)?;
+        Ok::<_, CrdtError>((current.version, document.save()))
+      })
+      .await?
+    },
+  };
+  Ok(doc_response(version, bytes))
+}
+
+#[derive(Deserialize)]
+struct SyncQuery {
+  /// Comma separated hashes of the changes the client has, in hex
+  #[serde(default)]
+  heads: String,
+}
+
+/// Merge the changes in the body into the document of a board, and answer with
+/// the changes the client is missing given its `heads`. If the merge changes
+/// the layout, it's stored as the next version as with any other write, so it
+/// needs no `If-Match`.
+#[post("/boards/{id}/crdt/sync")]
+async fn sync_doc(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  visitor: Visitor,
+  target_board: web::Path<String>,
+  query: web::Query<SyncQuery>,
+  changes: web::Bytes,
+) -> actix_web::Result<impl Responder> {
+  let heads = (query.heads.split(',').filter(|h| !h.is_empty()))
+    .map(|h| h.parse::<ChangeHash>())
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|e| CrdtError::Malformed(e.to_string()))?;
+  let target = target_board.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let (version, committed, bytes) = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      let current = find_board(conn, &target)?;
+      check_access(conn, &current, &visitor, Access::Edit)?;
+      let mut document = load_or_create(conn, &current, now)?;
+      if changes.is_empty() {
+        return Ok((current.version, false, document.save_after(&heads)));
+      }
+      document.load_incremental(&changes).map_err(|e| CrdtError::Malformed(e.to_string()))?;
+      let merged = hydrate(&document).map_err(|e| CrdtError::Unmergeable(e.to_string()))?;
+      let merged = BoardLayout::V2(merged);
+      let errors = merged.validate();
+      if !errors.is_empty() {
+        return Err(InvalidLayout(errors).into());
+      }
+      // changes that cancel out are kept, so that the client's heads stay known
+      store(conn, current.id, &mut document, now);
+      let unchanged =
+        parse_v2(&current.layout).is_some_and(|layout| BoardLayout::V2(layout) == merged);
+      let version = match unchanged {
+        true => current.version,
+        false => {
+          let merged = serde_json::to_string(&merged).unwrap();
+          commit_layout(conn, &current, merged, visitor.user_id, now)
+        },
+      };
+      Ok::<_, CrdtError>((version, !unchanged, document.save_after(&heads)))
+    }),
+  )
+  .await?;
+  if committed {
+    cache.invalidate(&target);
+    hub.changed(&target, version);
+  }
+  Ok(doc_response(version, bytes))
+}
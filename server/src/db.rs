@@ -2,17 +2,132 @@
 
 use std::env;
 
+use actix_web::{web, ResponseError};
+use diesel::connection::SimpleConnection;
 use diesel::prelude::Insertable;
-use diesel::{Queryable, Selectable, SqliteConnection};
+use diesel::r2d2::{ConnectionManager, CustomizeConnection};
+use diesel::{Connection, Queryable, Selectable, SqliteConnection};
 
 use crate::schema;
 
-pub type DbPool = r2d2::Pool<diesel::r2d2::ConnectionManager<SqliteConnection>>;
+pub type DbPool = r2d2::Pool<ConnectionManager<SqliteConnection>>;
+
+/// Lets concurrent requests queue up on the SQLite write lock instead of
+/// failing with `SQLITE_BUSY`, and lets readers proceed while a write is in
+/// progress.
+#[derive(Debug)]
+struct ConnOptions;
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for ConnOptions {
+  fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+    (conn.batch_execute("PRAGMA busy_timeout = 5000; PRAGMA journal_mode = WAL;"))
+      .map_err(diesel::r2d2::Error::QueryError)
+  }
+}
 
 pub fn create_pool() -> DbPool {
   let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-  let manager = diesel::r2d2::ConnectionManager::<SqliteConnection>::new(database_url);
-  (DbPool::builder().build(manager)).expect("database URL should be valid path to SQLite DB file")
+  let manager = ConnectionManager::<SqliteConnection>::new(database_url);
+  (DbPool::builder().connection_customizer(Box::new(ConnOptions)).build(manager))
+    .expect("database URL should be valid path to SQLite DB file")
+}
+
+/// A pool over a fresh in-memory database with every migration applied. It
+/// holds a single connection, since each one would get a database of its own.
+#[cfg(test)]
+pub fn test_pool() -> DbPool {
+  let manager = ConnectionManager::<SqliteConnection>::new(":memory:");
+  let pool = DbPool::builder().max_size(1).build(manager).unwrap();
+  let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+  let mut migrations = (std::fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()))
+    .filter(|path| path.is_dir())
+    .collect::<Vec<_>>();
+  migrations.sort();
+  for migration in migrations {
+    let up = std::fs::read_to_string(migration.join("up.sql")).unwrap();
+    pool.get().unwrap().batch_execute(&up).unwrap();
+  }
+  pool
+}
+
+fn run_in_tx<T, E>(
+  conn: &mut SqliteConnection,
+  immediate: bool,
+  f: impl FnOnce(&mut SqliteConnection) -> Result<T, E>,
+) -> Result<T, E> {
+  // The request error is smuggled past diesel so that it can still roll the
+  // transaction back
+  let mut failure = None;
+  let body = |conn: &mut SqliteConnection| {
+    f(conn).map_err(|e| {
+      failure = Some(e);
+      diesel::result::Error::RollbackTransaction
+    })
+  };
+  let result = match immediate {
+    true => conn.immediate_transaction(body),
+    false => conn.transaction(body),
+  };
+  match (result, failure) {
+    (Ok(t), _) => Ok(t),
+    (Err(_), Some(e)) => Err(e),
+    (Err(e), None) => panic!("Unexpected database error {e}"),
+  }
+}
+
+/// Run `f` in a transaction that takes the write lock up front with `BEGIN
+/// IMMEDIATE`. Any unit of work that may write should use this, because SQLite
+/// cannot upgrade a deferred transaction to a write transaction once another
+/// connection has written. Database errors are treated as bugs and panic like
+/// everywhere else, an `Err` returned by the closure rolls the transaction back
+/// and is passed on.
+pub fn atomic<T, E>(
+  conn: &mut SqliteConnection,
+  f: impl FnOnce(&mut SqliteConnection) -> Result<T, E>,
+) -> Result<T, E> {
+  run_in_tx(conn, true, f)
+}
+
+/// Run `f` on the blocking threadpool with exclusive use of a single pooled
+/// connection. Use this directly when part of the work shouldn't hold a
+/// transaction open, otherwise see [read] and [write].
+pub async fn run<T, E>(
+  pool: &DbPool,
+  f: impl FnOnce(&mut SqliteConnection) -> Result<T, E> + Send + 'static,
+) -> actix_web::Result<T>
+where
+  T: Send + 'static,
+  E: ResponseError + Send + 'static,
+{
+  let pool = pool.clone();
+  web::block(move || f(&mut pool.get().expect("Failed to obtain a database connection")))
+    .await?
+    .map_err(actix_web::Error::from)
+}
+
+/// Run a read-only unit of work on a single connection in a deferred
+/// transaction, so all of its queries see the same snapshot of the database.
+pub async fn read<T, E>(
+  pool: &DbPool,
+  f: impl FnOnce(&mut SqliteConnection) -> Result<T, E> + Send + 'static,
+) -> actix_web::Result<T>
+where
+  T: Send + 'static,
+  E: ResponseError + Send + 'static,
+{
+  run(pool, move |conn| run_in_tx(conn, false, f)).await
+}
+
+/// Run a unit of work that may write on a single connection in an [atomic]
+/// transaction.
+pub async fn write<T, E>(
+  pool: &DbPool,
+  f: impl FnOnce(&mut SqliteConnection) -> Result<T, E> + Send + 'static,
+) -> actix_web::Result<T>
+where
+  T: Send + 'static,
+  E: ResponseError + Send + 'static,
+{
+  run(pool, move |conn| atomic(conn, f)).await
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Queryable, Selectable, Insertable)]
@@ -40,9 +155,127 @@ pub struct Session {
 pub struct Board {
   pub id: i64,
   pub name: String,
-  pub url: i64,
+  pub url: String,
   pub version: i32,
-  pub owner_id: i64,
+  /// Missing for boards created without an account, until someone claims them
+  pub owner_id: Option<i64>,
   pub public_mut: bool,
   pub layout: String,
+  pub updated_at: i64,
+  /// One of the [common::Visibility] names
+  pub visibility: String,
+  /// Current slug in the owner's namespace
+  pub slug: Option<String>,
+  /// Set while the board is in the trash
+  pub deleted_at: Option<i64>,
+  /// Offered to the owner as a starting point for new boards
+  pub is_template: bool,
+  /// Digest of the key that lets anyone holding it edit, see [crate::edit_keys]
+  pub edit_key: Option<String>,
+  pub description: Option<String>,
+  /// An emoji, or the url of an image
+  pub icon: Option<String>,
+  /// Accent color, as `#rrggbb`
+  pub color: Option<String>,
+  pub created_at: i64,
+  /// Who made the current version, missing for anonymous edits
+  pub updated_by: Option<i64>,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::board_revision)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct BoardRevision {
+  pub board_id: i64,
+  pub version: i32,
+  pub layout: String,
+  pub author_id: Option<i64>,
+  pub created_at: i64,
+}
+
+/// Automerge document of a board edited collaboratively
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::board_crdt)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct BoardCrdt {
+  pub board_id: i64,
+  pub doc: Vec<u8>,
+  pub updated_at: i64,
+}
+
+/// Offer of a board to a new owner, waiting for them to accept
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::board_transfer)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct BoardTransfer {
+  pub board_id: i64,
+  pub from_id: i64,
+  pub to_id: i64,
+  pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::notification)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Notification {
+  pub id: i64,
+  pub user_id: i64,
+  /// One of the [common::NotificationKind] names
+  pub kind: String,
+  pub board_id: i64,
+  pub other_id: Option<i64>,
+  pub read: bool,
+  pub created_at: i64,
+}
+
+/// Former url of a moved board that redirects to its current one
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::board_alias)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct BoardAlias {
+  pub url: String,
+  pub board_id: i64,
+  pub expires_at: Option<i64>,
+  pub created_at: i64,
+}
+
+/// Slug a board has been reachable by, in its owner's namespace
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::board_slug)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct BoardSlug {
+  pub owner_id: i64,
+  pub slug: String,
+  pub board_id: i64,
+  pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::board_member)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct BoardMember {
+  pub board_id: i64,
+  pub user_id: i64,
+  /// One of the [common::BoardRole] names
+  pub role: String,
+  /// Invitations are pending until the user accepts them
+  pub accepted: bool,
+  pub invited_by: Option<i64>,
+  pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::share_link)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ShareLink {
+  pub token: String,
+  pub board_id: i64,
+  /// One of the [common::ShareMode] names
+  pub mode: String,
+  pub label: Option<String>,
+  pub expires_at: Option<i64>,
+  pub max_uses: Option<i32>,
+  pub uses: i32,
+  pub created_by: i64,
+  pub created_at: i64,
 }
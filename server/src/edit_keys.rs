@@ -0,0 +1,214 @@
+//! Boards created without an account. Instead of an owner they get an edit
+//! key, a secret that lets whoever holds it edit the board until it's rotated.
+//! Owned boards can hand out keys too. Someone who registers later can claim
+//! the board into their account, which retires its key.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::time::SystemTime;
+
+use actix_web::http::header::ETag;
+use actix_web::http::StatusCode;
+use actix_web::{delete, post, web, HttpResponse, Responder, ResponseError};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use common::{clone, epoch_secs, AnonymousBoard, EditKey, NewBoardForm};
+use diesel::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::auth::{AuthdUser, Visitor};
+use crate::boards::{
+  canonical_layout, check_access, find_board, insert_board, version_tag, Access, BoardNotFound,
+};
+use crate::cache::BoardCache;
+use crate::db::{self, Board, DbPool};
+use crate::events::Hub;
+use crate::ids;
+
+pub fn cfg_edit_keys(cfg: &mut web::ServiceConfig) {
+  cfg.service(new_anonymous_board).service(rotate_key).service(revoke_key).service(claim_board);
+}
+
+/// Header carrying the edit key of the board a request is about
+pub const EDIT_KEY: &str = "X-Edit-Key";
+
+/// Only a digest is stored, so that the database doesn't hand out edit access
+fn digest(key: &str) -> String { URL_SAFE_NO_PAD.encode(Sha256::digest(key.as_bytes())) }
+
+/// Access granted by the edit key of a board
+pub fn access_of(board: &Board, key: &str) -> Option<Access> {
+  (board.edit_key.as_deref() == Some(&digest(key))).then_some(Access::Edit)
+}
+
+fn holds_key(board: &Board, visitor: &Visitor) -> bool {
+  visitor.edit_key.as_deref().and_then(|key| access_of(board, key)).is_some()
+}
+
+/// Give a board a new edit key, replacing the one it had
+fn issue(conn: &mut SqliteConnection, board_id: i64) -> String {
+  use crate::schema::board::dsl::*;
+  let key = ids::secret();
+  diesel::update(board.find(board_id)).set(edit_key.eq(digest(&key))).execute(conn).unwrap();
+  key
+}
+
+#[derive(Clone, Debug)]
+pub enum EditKeyError {
+  Board(BoardNotFound),
+  /// Boards with an owner can't be claimed
+  Owned,
+  /// The key is the only way to edit a board without an owner
+  Ownerless,
+}
+impl From<BoardNotFound> for EditKeyError {
+  fn from(value: BoardNotFound) -> Self { Self::Board(value) }
+}
+impl fmt::Display for EditKeyError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Board(e) => write!(f, "{e}"),
+      Self::Owned => write!(f, "The board already belongs to an account"),
+      Self::Ownerless =>
+        write!(f, "The board belongs to nobody, its key can only be rotated, or claimed first"),
+    }
+  }
+}
+impl ResponseError for EditKeyError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Board(e) => e.status_code(),
+      Self::Owned | Self::Ownerless => StatusCode::CONFLICT,
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Self::Board(e) => e.error_response(),
+      Self::Owned | Self::Ownerless =>
+        HttpResponse::build(self.status_code()).body(self.to_string()),
+    }
+  }
+}
+
+/// Create a board that belongs to nobody. The answer carries its edit key,
+/// which is the only way to edit it.
+#[post("/new_anonymous_board")]
+async fn new_anonymous_board(
+  pool: web::Data<DbPool>,
+  form: web::Json<NewBoardForm>,
+) -> actix_web::Result<impl Responder> {
+  let NewBoardForm { layout, name, public_mut, visibility } = form.into_inner();
+  let layout = canonical_layout(&layout)?;
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let created = db::write(&pool, move |conn| {
+    let fresh = insert_board(conn, None, name, public_mut, visibility, layout, now);
+    let edit_key = issue(conn, fresh.id);
+    Ok::<_, Infallible>(AnonymousBoard { id: fresh.id, url: fresh.url, edit_key })
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(created))
+}
+
+/// Replace the edit key of a board, so that only those given the new one keep
+/// editing. Allowed with the current key, or for whoever manages the board,
+/// who can also give a board its first key this way.
+#[post("/boards/{id}/edit_key")]
+async fn rotate_key(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  visitor: Visitor,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let edit_key = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      let current = find_board(conn, &target)?;
+      if !holds_key(&current, &visitor) {
+        check_access(conn, &current, &visitor, Access::Manage)?;
+      }
+      Ok::<_, BoardNotFound>(issue(conn, current.id))
+    }),
+  )
+  .await?;
+  // cached copies still carry the old key
+  cache.invalidate(&target);
+  Ok(HttpResponse::Ok().json(EditKey { edit_key }))
+}
+
+/// Stop a board's edit key from working, without handing out a new one. Boards
+/// without an owner would be left for nobody to edit, so theirs can't be
+/// revoked.
+#[delete("/boards/{id}/edit_key")]
+async fn revoke_key(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  visitor: Visitor,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  db::write(
+    &pool,
+    clone!(target; move |conn| {
+      use crate::schema::board::dsl::*;
+      let current = find_board(conn, &target)?;
+      if !holds_key(&current, &visitor) {
+        check_access(conn, &current, &visitor, Access::Manage)?;
+      }
+      if current.owner_id.is_none() {
+        return Err(EditKeyError::Ownerless);
+      }
+      (diesel::update(board.find(current.id)))
+        .set(edit_key.eq(None::<String>))
+        .execute(conn)
+        .unwrap();
+      Ok(())
+    }),
+  )
+  .await?;
+  cache.invalidate(&target);
+  Ok(HttpResponse::NoContent().finish())
+}
+
+/// Make the user the owner of a board that belongs to nobody, proven by its
+/// edit key. The key stops working, the new owner can share the board through
+/// members, share links or a fresh key.
+#[post("/boards/{id}/claim")]
+async fn claim_board(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  ses_u: AuthdUser,
+  visitor: Visitor,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let new_version = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      use crate::schema::board::dsl::*;
+      let current = find_board(conn, &target)?;
+      if !holds_key(&current, &visitor) {
+        return Err(BoardNotFound::denied().into());
+      }
+      if current.owner_id.is_some() {
+        return Err(EditKeyError::Owned);
+      }
+      diesel::update(board.find(current.id))
+        .set((
+          owner_id.eq(ses_u.id),
+          edit_key.eq(None::<String>),
+          version.eq(version + 1),
+          updated_at.eq(now),
+          updated_by.eq(ses_u.id),
+        ))
+        .execute(conn)
+        .unwrap();
+      Ok(current.version + 1)
+    }),
+  )
+  .await?;
+  cache.invalidate(&target);
+  hub.changed(&target, new_version);
+  Ok(HttpResponse::NoContent().insert_header(ETag(version_tag(new_version))).finish())
+}
@@ -0,0 +1,286 @@
+use std::fmt;
+use std::time::SystemTime;
+
+use actix_web::http::header::{ETag, IfMatch};
+use actix_web::http::StatusCode;
+use actix_web::{delete, post, web, HttpResponse, Responder, ResponseError};
+use common::layout::{BoardLayout, BoardLayoutV2, Entry, PlaceError};
+use common::{
+  clone, epoch_secs, EntryDetails, EntryPatch, FolderPatch, ItemPatch, MoveEntryForm, NewEntryForm,
+};
+use diesel::prelude::*;
+
+use crate::auth::Visitor;
+use crate::boards::{
+  check_access, check_version, commit_layout, find_board, parse_v2, required_tags, version_tag,
+  Access, BoardNotFound, InvalidLayout, WriteError,
+};
+use crate::cache::BoardCache;
+use crate::db::{self, Board, DbPool};
+use crate::events::Hub;
+
+pub fn cfg_entries(cfg: &mut web::ServiceConfig) {
+  cfg.service(add_entry).service(edit_entry).service(delete_entry).service(move_entry);
+}
+
+#[derive(Clone, Debug)]
+pub enum EntryError {
+  Write(WriteError),
+  NoEntry(u64),
+  /// A folder patch for an item or the other way round
+  WrongKind(u64),
+  Place(PlaceError),
+  Invalid(InvalidLayout),
+  /// The layout isn't in a format the server understands, so it has no entries
+  Opaque,
+}
+impl From<BoardNotFound> for EntryError {
+  fn from(value: BoardNotFound) -> Self { Self::Write(value.into()) }
+}
+impl From<WriteError> for EntryError {
+  fn from(value: WriteError) -> Self { Self::Write(value) }
+}
+impl From<PlaceError> for EntryError {
+  fn from(value: PlaceError) -> Self { Self::Place(value) }
+}
+impl From<InvalidLayout> for EntryError {
+  fn from(value: InvalidLayout) -> Self { Self::Invalid(value) }
+}
+impl fmt::Display for EntryError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Write(e) => write!(f, "{e}"),
+      Self::NoEntry(id) => write!(f, "The board has no entry with the id {id}"),
+      Self::WrongKind(id) => write!(f, "The entry {id} isn't of the kind the patch is for"),
+      Self::Place(e) => write!(f, "{e}"),
+      Self::Invalid(e) => write!(f, "{e}"),
+      Self::Opaque =>
+        write!(f, "This board's layout isn't in a format whose entries can be edited"),
+    }
+  }
+}
+impl ResponseError for EntryError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Write(e) => e.status_code(),
+      Self::NoEntry(_) => StatusCode::NOT_FOUND,
+      Self::WrongKind(_) | Self::Place(_) | Self::Opaque => StatusCode::UNPROCESSABLE_ENTITY,
+      Self::Invalid(e) => e.status_code(),
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Self::Write(e) => e.error_response(),
+      Self::Invalid(e) => e.error_response(),
+      _ => HttpResponse::build(self.status_code()).body(self.to_string()),
+    }
+  }
+}
+
+/// Entry writes apply to whatever the layout is when they arrive, so unlike
+/// whole layout edits they don't need an `If-Match`. It's still honored.
+fn optional_tags(ifmatch: Option<web::Header<IfMatch>>) -> actix_web::Result<Option<Vec<i32>>> {
+  match ifmatch.as_deref() {
+    // a missing header parses as an empty list
+    None => Ok(None),
+    Some(IfMatch::Items(itv)) if itv.is_empty() => Ok(None),
+    Some(_) => required_tags(ifmatch),
+  }
+}
+
+/// Check that the visitor may edit a board and read its layout for editing
+fn editable(
+  conn: &mut SqliteConnection,
+  current: &Board,
+  visitor: &Visitor,
+  tags: Option<&[i32]>,
+) -> Result<BoardLayoutV2, EntryError> {
+  check_access(conn, current, visitor, Access::Edit)?;
+  check_version(current, tags, true)?;
+  parse_v2(&current.layout).ok_or(EntryError::Opaque)
+}
+
+/// Validate an edited layout and store it as the next version of the board
+fn commit(
+  conn: &mut SqliteConnection,
+  current: &Board,
+  layout: BoardLayoutV2,
+  author: Option<i64>,
+  now: i64,
+) -> Result<i32, EntryError> {
+  let layout = BoardLayout::V2(layout);
+  let errors = layout.validate();
+  if !errors.is_empty() {
+    return Err(InvalidLayout(errors).into());
+  }
+  Ok(commit_layout(conn, current, serde_json::to_string(&layout).unwrap(), author, now))
+}
+
+fn entry_response(status: StatusCode, details: EntryDetails) -> HttpResponse {
+  HttpResponse::build(status).insert_header(ETag(version_tag(details.version))).json(details)
+}
+
+#[post("/boards/{id}/entries")]
+async fn add_entry(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  visitor: Visitor,
+  target_board: web::Path<String>,
+  form: web::Json<NewEntryForm>,
+  ifmatch: Option<web::Header<IfMatch>>,
+) -> actix_web::Result<impl Responder> {
+  let tags = optional_tags(ifmatch)?;
+  let NewEntryForm { at, mut entry } = form.into_inner();
+  let target = target_board.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let details = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      let current = find_board(conn, &target)?;
+      let mut layout = editable(conn, &current, &visitor, tags.as_deref())?;
+      entry.renumber(layout.fresh_id());
+      layout.place(&at, entry.clone())?;
+      let version = commit(conn, &current, layout, visitor.user_id, now)?;
+      Ok::<_, EntryError>(EntryDetails { board: current.url.into(), version, entry })
+    }),
+  )
+  .await?;
+  cache.invalidate(&target);
+  hub.changed(&target, details.version);
+  Ok(entry_response(StatusCode::CREATED, details))
+}
+
+#[post("/boards/{id}/entries/{entry}")]
+async fn edit_entry(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  visitor: Visitor,
+  path: web::Path<(String, u64)>,
+  patch: web::Json<EntryPatch>,
+  ifmatch: Option<web::Header<IfMatch>>,
+) -> actix_web::Result<impl Responder> {
+  let tags = optional_tags(ifmatch)?;
+  let (target, entry_id) = path.into_inner();
+  let patch = patch.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let details = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      let current = find_board(conn, &target)?;
+      let mut layout = editable(conn, &current, &visitor, tags.as_deref())?;
+      let entry = layout.find_mut(entry_id).ok_or(EntryError::NoEntry(entry_id))?;
+      // an empty string clears an optional field
+      let clear_or_set = |field: &mut Option<String>, value: Option<String>| {
+        if let Some(value) = value {
+          *field = (!value.is_empty()).then_some(value);
+        }
+      };
+      match (&mut *entry, patch) {
+        (Entry::Folder(folder), EntryPatch::Folder(FolderPatch { title, collapsed })) => {
+          folder.title = title.unwrap_or_else(|| folder.title.clone());
+          folder.collapsed = collapsed.unwrap_or(folder.collapsed);
+        },
+        (Entry::Item(item), EntryPatch::Item(ItemPatch { kind, icon, description, color })) => {
+          item.kind = kind.unwrap_or_else(|| item.kind.clone());
+          clear_or_set(&mut item.icon, icon);
+          clear_or_set(&mut item.description, description);
+          clear_or_set(&mut item.color, color);
+        },
+        _ => return Err(EntryError::WrongKind(entry_id)),
+      }
+      let entry = entry.clone();
+      let version = commit(conn, &current, layout, visitor.user_id, now)?;
+      Ok(EntryDetails { board: current.url.into(), version, entry })
+    }),
+  )
+  .await?;
+  cache.invalidate(&target);
+  hub.changed(&target, details.version);
+  Ok(entry_response(StatusCode::OK, details))
+}
+
+/// Delete an entry, and everything in it if it's a folder
+#[delete("/boards/{id}/entries/{entry}")]
+async fn delete_entry(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  visitor: Visitor,
+  path: web::Path<(String, u64)>,
+  ifmatch: Option<web::Header<IfMatch>>,
+) -> actix_web::Result<impl Responder> {
+  let tags = optional_tags(ifmatch)?;
+  let (target, entry_id) = path.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let new_version = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      let current = find_board(conn, &target)?;
+      let mut layout = editable(conn, &current, &visitor, tags.as_deref())?;
+      layout.take(entry_id).ok_or(EntryError::NoEntry(entry_id))?;
+      commit(conn, &current, layout, visitor.user_id, now)
+    }),
+  )
+  .await?;
+  cache.invalidate(&target);
+  hub.changed(&target, new_version);
+  Ok(HttpResponse::NoContent().insert_header(ETag(version_tag(new_version))).finish())
+}
+
+/// Reorder an entry within its board, or move it to another board in the same
+/// transaction so that it's never in both or neither. `If-Match` applies to the
+/// board the entry comes from.
+#[post("/boards/{id}/entries/{entry}/move")]
+async fn move_entry(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  visitor: Visitor,
+  path: web::Path<(String, u64)>,
+  form: web::Json<MoveEntryForm>,
+  ifmatch: Option<web::Header<IfMatch>>,
+) -> actix_web::Result<impl Responder> {
+  let tags = optional_tags(ifmatch)?;
+  let (target, entry_id) = path.into_inner();
+  let MoveEntryForm { board: to_board, at } = form.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let (touched, details) = db::write(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    let mut layout = editable(conn, &current, &visitor, tags.as_deref())?;
+    let mut entry = layout.take(entry_id).ok_or(EntryError::NoEntry(entry_id))?;
+    // the destination comes from the body, where a redirect can't point to
+    // its new url, so aliases are followed instead
+    let dest = match to_board {
+      None => None,
+      Some(to) => Some(find_board(conn, &to.0).or_else(|e| match e.moved() {
+        Some(moved) => find_board(conn, &moved.to),
+        None => Err(e),
+      })?),
+    };
+    match dest.filter(|dest| dest.id != current.id) {
+      None => {
+        layout.place(&at, entry.clone())?;
+        let version = commit(conn, &current, layout, visitor.user_id, now)?;
+        let details = EntryDetails { board: current.url.clone().into(), version, entry };
+        Ok::<_, EntryError>((vec![(current.url, version)], details))
+      },
+      Some(dest) => {
+        let mut dest_layout = editable(conn, &dest, &visitor, None)?;
+        entry.renumber(dest_layout.fresh_id());
+        dest_layout.place(&at, entry.clone())?;
+        let from_version = commit(conn, &current, layout, visitor.user_id, now)?;
+        let version = commit(conn, &dest, dest_layout, visitor.user_id, now)?;
+        let details = EntryDetails { board: dest.url.clone().into(), version, entry };
+        Ok((vec![(current.url, from_version), (dest.url, version)], details))
+      },
+    }
+  })
+  .await?;
+  for (url, version) in &touched {
+    cache.invalidate(url);
+    hub.changed(url, *version);
+  }
+  Ok(entry_response(StatusCode::OK, details))
+}
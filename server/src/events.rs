@@ -0,0 +1,225 @@
+use std::collections::{HashSet, VecDeque};
+use std::convert::Infallible;
+use std::time::Duration;
+
+use actix_web::http::header::{ContentEncoding, CACHE_CONTROL};
+use actix_web::{get, web, HttpResponse, Responder};
+use common::{clone, BoardUrl, ChangeEvent};
+use diesel::SqliteConnection;
+use futures_util::stream;
+use itertools::Itertools;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{self};
+
+use crate::auth::{AuthError, AuthdUser, Visitor};
+use crate::batch::{TooManyBoards, MAX_BATCH};
+use crate::bearer_token::{parse_token, TokenError};
+use crate::boards::{check_access, find_board, Access};
+use crate::db::{self, Board, DbPool};
+
+pub fn cfg_events(cfg: &mut web::ServiceConfig) { cfg.service(subscribe); }
+
+/// How many changes a slow stream may fall behind before it misses some
+const CAPACITY: usize = 1024;
+/// Comments are sent this often when nothing happens, so that proxies don't
+/// close the connection as idle
+const KEEPALIVE: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+enum Change {
+  Board(ChangeEvent),
+  Layout(i64),
+}
+
+/// Fans changes out to every open event stream, each of which picks the ones
+/// its subscriber watches. Write handlers publish once their transaction has
+/// committed, right after invalidating the [crate::cache::BoardCache].
+pub struct Hub {
+  sender: broadcast::Sender<Change>,
+}
+impl Hub {
+  pub fn new() -> Self { Self { sender: broadcast::channel(CAPACITY).0 } }
+
+  /// Sending only fails when nobody is listening, which is fine
+  fn publish(&self, change: Change) { self.sender.send(change).ok(); }
+
+  pub fn changed(&self, url: &str, version: i32) {
+    self.publish(Change::Board(ChangeEvent::Board { url: BoardUrl(url.to_string()), version }));
+  }
+
+  pub fn moved(&self, from: &str, to: &str) {
+    self.publish(Change::Board(ChangeEvent::Moved {
+      from: BoardUrl(from.to_string()),
+      to: BoardUrl(to.to_string()),
+    }));
+  }
+
+  pub fn deleted(&self, url: &str) {
+    self.publish(Change::Board(ChangeEvent::Deleted { url: BoardUrl(url.to_string()) }));
+  }
+
+  pub fn layout(&self, user_id: i64) { self.publish(Change::Layout(user_id)); }
+}
+
+struct Subscription {
+  pool: web::Data<DbPool>,
+  visitor: Visitor,
+  changes: broadcast::Receiver<Change>,
+  /// Urls of the watched boards, updated as they move
+  boards: HashSet<String>,
+  /// Set if the subscriber watches their view layout
+  layout_of: Option<i64>,
+  /// Events to send before any change
+  backlog: VecDeque<ChangeEvent>,
+}
+impl Subscription {
+  /// Whether the subscriber should hear about a change
+  fn watches(&mut self, change: &Change) -> bool {
+    match change {
+      Change::Board(ChangeEvent::Board { url, .. } | ChangeEvent::Deleted { url }) =>
+        self.boards.contains(&url.0),
+      Change::Board(ChangeEvent::Moved { from, to }) => {
+        let watched = self.boards.remove(&from.0);
+        if watched {
+          self.boards.insert(to.0.clone());
+        }
+        watched
+      },
+      Change::Board(ChangeEvent::Layout | ChangeEvent::Missed) => false,
+      Change::Layout(user_id) => self.layout_of == Some(*user_id),
+    }
+  }
+
+  /// Access may have been lost since subscribing, to a change of visibility or
+  /// membership. A board the subscriber can no longer view stops being watched
+  /// and is reported as deleted under the url they knew it by.
+  async fn recheck(&mut self, event: ChangeEvent) -> ChangeEvent {
+    let (known, current) = match &event {
+      ChangeEvent::Board { url, .. } => (url.clone(), url.clone()),
+      ChangeEvent::Moved { from, to } => (from.clone(), to.clone()),
+      ChangeEvent::Deleted { .. } | ChangeEvent::Layout | ChangeEvent::Missed => return event,
+    };
+    let (visitor, target) = (self.visitor.clone(), current.0.clone());
+    let viewable = db::read(&self.pool, move |conn| {
+      Ok::<_, Infallible>(viewable(conn, &target, &visitor).is_some())
+    });
+    if viewable.await.unwrap_or(false) {
+      return event;
+    }
+    self.boards.remove(&current.0);
+    ChangeEvent::Deleted { url: known }
+  }
+}
+
+/// The board at `target`, followed to where it went if the alias was kept, if
+/// the visitor may view it
+fn viewable(conn: &mut SqliteConnection, target: &str, visitor: &Visitor) -> Option<Board> {
+  let found = find_board(conn, target);
+  let found = match found.as_ref().err().and_then(|e| e.moved()) {
+    Some(moved) => find_board(conn, &moved.to.clone()),
+    None => found,
+  };
+  found.ok().filter(|board| check_access(conn, board, visitor, Access::View).is_ok())
+}
+
+fn frame(event: &ChangeEvent) -> web::Bytes {
+  format!("data: {}\n\n", serde_json::to_string(event).unwrap()).into()
+}
+
+async fn next_frame(
+  mut sub: Subscription,
+) -> Option<(Result<web::Bytes, Infallible>, Subscription)> {
+  if let Some(event) = sub.backlog.pop_front() {
+    return Some((Ok(frame(&event)), sub));
+  }
+  loop {
+    let event = match actix_web::rt::time::timeout(KEEPALIVE, sub.changes.recv()).await {
+      Err(_) => return Some((Ok(web::Bytes::from_static(b": keepalive\n\n")), sub)),
+      Ok(Err(RecvError::Closed)) => return None,
+      Ok(Err(RecvError::Lagged(_))) => ChangeEvent::Missed,
+      Ok(Ok(change)) if sub.watches(&change) => match change {
+        Change::Board(event) => sub.recheck(event).await,
+        Change::Layout(_) => ChangeEvent::Layout,
+      },
+      Ok(Ok(_)) => continue,
+    };
+    return Some((Ok(frame(&event)), sub));
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EventsQuery {
+  /// Comma separated urls of the boards to watch
+  #[serde(default)]
+  boards: String,
+  /// Watch the user's view layout as well
+  #[serde(default)]
+  layout: bool,
+  /// For `EventSource`, which can't set an `Authorization` header
+  access_token: Option<String>,
+}
+
+/// Stream changes to boards and the user's view layout as Server-Sent Events.
+/// Access to the boards is checked when subscribing and again with each of
+/// their changes, those the visitor can't view are reported as deleted and not
+/// watched.
+#[get("/events")]
+async fn subscribe(
+  pool: web::Data<DbPool>,
+  hub: web::Data<Hub>,
+  mut visitor: Visitor,
+  query: web::Query<EventsQuery>,
+) -> actix_web::Result<impl Responder> {
+  let EventsQuery { boards, layout, access_token } = query.into_inner();
+  if let Some(token) = access_token {
+    let token = parse_token(&token)
+      .and_then(|token| (!token.expired()).then_some(token).ok_or(TokenError::Expired));
+    visitor.user_id = Some(token.map_err(AuthError::Token).and_then(AuthdUser::from_token)?.id);
+  }
+  let layout_of = match (layout, visitor.user_id) {
+    (true, None) => return Err(AuthError::Token(TokenError::NoAuth).into()),
+    (true, Some(user_id)) => Some(user_id),
+    (false, _) => None,
+  };
+  let targets = boards.split(',').filter(|url| !url.is_empty()).map(str::to_string).unique();
+  let targets = targets.collect::<Vec<_>>();
+  if MAX_BATCH < targets.len() {
+    return Err(TooManyBoards.into());
+  }
+  // subscribed before reading the current versions so that no change falls
+  // between the two
+  let changes = hub.sender.subscribe();
+  let (boards, backlog) = db::read(
+    &pool,
+    clone!(visitor; move |conn| {
+      let (mut boards, mut backlog) = (HashSet::new(), VecDeque::new());
+      for target in targets {
+        // boards the visitor can't view aren't watched, so that nothing is
+        // learnt about them
+        let event = match viewable(conn, &target, &visitor) {
+          None => ChangeEvent::Deleted { url: target.into() },
+          Some(board) if board.url != target =>
+            ChangeEvent::Moved { from: target.into(), to: board.url.into() },
+          Some(board) => ChangeEvent::Board { url: board.url.into(), version: board.version },
+        };
+        if let ChangeEvent::Moved { to: url, .. } | ChangeEvent::Board { url, .. } = &event {
+          boards.insert(url.0.clone());
+        }
+        backlog.push_back(event);
+      }
+      Ok::<_, Infallible>((boards, backlog))
+    }),
+  )
+  .await?;
+  let sub = Subscription { pool, visitor, changes, boards, layout_of, backlog };
+  Ok(
+    HttpResponse::Ok()
+      .content_type("text/event-stream")
+      .insert_header((CACHE_CONTROL, "no-cache"))
+      // compression would hold events back until enough of them pile up
+      .insert_header(ContentEncoding::Identity)
+      .streaming(stream::unfold(sub, next_frame)),
+  )
+}
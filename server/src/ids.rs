@@ -0,0 +1,40 @@
+use std::time::SystemTime;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use diesel::result::{DatabaseErrorKind, Error};
+use diesel::QueryResult;
+
+/// How many fresh ids [insert_unique] tries before giving up
+const ATTEMPTS: usize = 8;
+
+/// 128 random bits in URL-safe base64, which can't be enumerated
+pub fn secret() -> String { URL_SAFE_NO_PAD.encode(rand::random::<[u8; 16]>()) }
+
+/// Public identifier for a board
+pub fn board_url() -> String { secret() }
+
+/// Row id ordered by creation time like a UUIDv7 or ULID, but squeezed into a
+/// positive i64: 42 bits of milliseconds, good until 2109, followed by 21
+/// random bits. Collisions are possible within a millisecond, so inserts go
+/// through [insert_unique].
+pub fn row_id() -> i64 {
+  let millis = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+  let millis = (millis as i64) & ((1 << 42) - 1);
+  (millis << 21) | (rand::random::<u32>() as i64 & ((1 << 21) - 1))
+}
+
+/// Run a write that uses freshly generated ids, generating new ones and trying
+/// again while it violates a uniqueness constraint. Any other uniqueness
+/// constraint on the table must be checked beforehand, or this gives up after
+/// a few attempts.
+pub fn insert_unique<T>(mut attempt: impl FnMut() -> QueryResult<T>) -> T {
+  for _ in 0..ATTEMPTS {
+    match attempt() {
+      Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => continue,
+      Err(e) => panic!("Unexpected database error {e}"),
+      Ok(value) => return value,
+    }
+  }
+  panic!("No unique id found in {ATTEMPTS} attempts")
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+
+use actix_web::http::StatusCode;
+use actix_web::{get, web, HttpResponse, Responder, ResponseError};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use common::layout::{BoardLayout, Entry};
+use common::versioned::Versioned;
+use common::{BoardListing, BoardPage, BoardRole, Visibility};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{AuthError, AuthdUser};
+use crate::boards::details;
+use crate::db::{self, Board, DbPool};
+use crate::schema::{board, board_member};
+
+pub fn cfg_listing(cfg: &mut web::ServiceConfig) { cfg.service(list_boards); }
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Filter {
+  /// Owned by or shared with the user
+  #[default]
+  Mine,
+  Owned,
+  /// The user is a member who accepted the invitation
+  Shared,
+  /// Templates among the user's boards and the public ones
+  Templates,
+  /// Anyone's public boards, no account needed
+  Public,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Sort {
+  /// Most recently updated first
+  #[default]
+  Updated,
+  Name,
+  /// Newest first
+  Created,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListQuery {
+  #[serde(default)]
+  filter: Filter,
+  /// Only boards whose name contains this, ignoring ASCII case
+  q: Option<String>,
+  #[serde(default)]
+  sort: Sort,
+  limit: Option<i64>,
+  cursor: Option<String>,
+}
+
+/// Sort key and id of the last board of a page, which the next page starts
+/// after. Keyset pagination stays consistent when boards are added meanwhile.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Cursor {
+  Updated(i64, i64),
+  Name(String, i64),
+  Created(i64, i64),
+}
+impl Cursor {
+  fn after(sort: Sort, board: &Board) -> Self {
+    match sort {
+      Sort::Updated => Self::Updated(board.updated_at, board.id),
+      Sort::Name => Self::Name(board.name.clone(), board.id),
+      Sort::Created => Self::Created(board.created_at, board.id),
+    }
+  }
+  fn encode(&self) -> String { URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap()) }
+  /// Timestamps look the same whichever they are, so the sort order tells
+  fn decode(sort: Sort, text: &str) -> Option<Self> {
+    let json = URL_SAFE_NO_PAD.decode(text).ok()?;
+    match sort {
+      Sort::Updated => serde_json::from_slice(&json).ok().map(|(at, id)| Self::Updated(at, id)),
+      Sort::Name => serde_json::from_slice(&json).ok().map(|(name, id)| Self::Name(name, id)),
+      Sort::Created => serde_json::from_slice(&json).ok().map(|(at, id)| Self::Created(at, id)),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct BadCursor;
+impl fmt::Display for BadCursor {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "The cursor doesn't come from a listing with this sort order")
+  }
+}
+impl ResponseError for BadCursor {
+  fn status_code(&self) -> StatusCode { StatusCode::BAD_REQUEST }
+}
+
+fn item_count(layout: &str) -> usize {
+  let layout = serde_json::from_str::<BoardLayout>(layout).map(|layout| layout.upgrade());
+  let mut count = 0;
+  if let Ok(BoardLayout::V2(v2)) = layout {
+    v2.walk(|_, entry| count += matches!(entry, Entry::Item(_)) as usize);
+  }
+  count
+}
+
+/// Escape the wildcards of `LIKE` in user input, using `\` as the escape
+fn like_pattern(text: &str) -> String {
+  let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+  format!("%{escaped}%")
+}
+
+#[get("/boards")]
+async fn list_boards(
+  pool: web::Data<DbPool>,
+  ses_u: Result<AuthdUser, AuthError>,
+  query: web::Query<ListQuery>,
+) -> actix_web::Result<impl Responder> {
+  let ListQuery { filter, q, sort, limit, cursor } = query.into_inner();
+  let user_id = match (filter, ses_u) {
+    (Filter::Public, ses_u) => ses_u.ok().map(|u| u.id),
+    (_, ses_u) => Some(ses_u?.id),
+  };
+  let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+  let cursor = match cursor {
+    None => None,
+    Some(text) => Some(Cursor::decode(sort, &text).ok_or(BadCursor)?),
+  };
+  let mut rows = db::read(&pool, move |conn| {
+    // ids are positive, so anonymous visitors match no membership
+    let uid = user_id.unwrap_or(-1);
+    let membership = board_member::table.on(
+      (board_member::board_id.eq(board::id))
+        .and(board_member::user_id.eq(uid))
+        .and(board_member::accepted.eq(true)),
+    );
+    let mut query = (board::table.left_join(membership))
+      .filter(board::deleted_at.is_null())
+      .select((Board::as_select(), board_member::role.nullable()))
+      .into_boxed();
+    let shared = || board_member::user_id.nullable().is_not_null();
+    let public = || board::visibility.eq(Visibility::Public.as_str());
+    query = match filter {
+      Filter::Mine => query.filter(board::owner_id.eq(uid).or(shared())),
+      Filter::Owned => query.filter(board::owner_id.eq(uid)),
+      Filter::Shared => query.filter(shared()),
+      Filter::Templates => query
+        .filter(board::is_template.eq(true))
+        .filter(board::owner_id.eq(uid).or(shared()).or(public())),
+      Filter::Public => query.filter(public()),
+    };
+    if let Some(q) = q {
+      query = query.filter(board::name.like(like_pattern(&q)).escape('\\'));
+    }
+    query = match cursor {
+      None => query,
+      Some(Cursor::Updated(updated_at, id)) => query.filter(
+        (board::updated_at.lt(updated_at))
+          .or(board::updated_at.eq(updated_at).and(board::id.lt(id))),
+      ),
+      Some(Cursor::Name(name, id)) =>
+        query.filter(board::name.gt(name.clone()).or(board::name.eq(name).and(board::id.gt(id)))),
+      Some(Cursor::Created(created_at, id)) => query.filter(
+        (board::created_at.lt(created_at))
+          .or(board::created_at.eq(created_at).and(board::id.lt(id))),
+      ),
+    };
+    query = match sort {
+      Sort::Updated => query.order((board::updated_at.desc(), board::id.desc())),
+      Sort::Name => query.order((board::name.asc(), board::id.asc())),
+      Sort::Created => query.order((board::created_at.desc(), board::id.desc())),
+    };
+    let rows: Vec<(Board, Option<String>)> = query.limit(limit + 1).load(conn).unwrap();
+    Ok::<_, std::convert::Infallible>(rows)
+  })
+  .await?;
+  // one more row than requested tells whether there is a next page
+  let next_cursor = match rows.len() as i64 > limit {
+    true => {
+      rows.truncate(limit as usize);
+      rows.last().map(|(last, _)| Cursor::after(sort, last).encode())
+    },
+    false => None,
+  };
+  let boards = (rows.into_iter())
+    .map(|(board, role)| BoardListing {
+      details: details(&board),
+      role: role.as_deref().and_then(BoardRole::parse),
+      item_count: item_count(&board.layout),
+    })
+    .collect();
+  Ok(HttpResponse::Ok().json(BoardPage { boards, next_cursor }))
+}
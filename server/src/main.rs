@@ -1,32 +1,104 @@
 #![feature(trivial_bounds)]
 #![feature(ready_into_inner)]
 
+mod aliases;
 mod auth;
+mod batch;
 mod bearer_token;
 mod boards;
+mod cache;
+mod crdt;
 mod db;
+mod edit_keys;
+mod entries;
+mod events;
+mod ids;
+mod listing;
+mod members;
+mod migrate;
+mod notifications;
+mod revisions;
 mod schema;
+mod shares;
+mod slugs;
+mod templates;
+mod transfers;
+mod trash;
 mod views;
 
+use std::env;
+
 use actix_cors::Cors;
-use actix_web::middleware::Logger;
+use actix_web::dev::Service;
+use actix_web::middleware::{Compress, Logger};
 use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
+use aliases::cfg_aliases;
 use auth::cfg_auth;
+use batch::cfg_batch;
 use boards::cfg_boards;
+use cache::BoardCache;
+use crdt::cfg_crdt;
 use db::create_pool;
 use dotenvy::dotenv;
+use edit_keys::cfg_edit_keys;
+use entries::cfg_entries;
+use events::{cfg_events, Hub};
+use futures_util::FutureExt;
+use listing::cfg_listing;
+use members::cfg_members;
+use notifications::cfg_notifications;
+use revisions::cfg_revisions;
+use shares::cfg_shares;
+use slugs::cfg_slugs;
+use templates::cfg_templates;
+use transfers::cfg_transfers;
+use trash::cfg_trash;
 use views::cfg_views;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
   dotenv().ok();
+  if env::args().nth(1).as_deref() == Some("migrate-layouts") {
+    let (boards, users) = migrate::migrate_all(&mut create_pool().get().unwrap());
+    println!("Upgraded the layouts of {boards} boards and {users} users");
+    return Ok(());
+  }
+  // shared by all workers so that the pool size bounds the number of open
+  // connections
+  let pool = web::Data::new(create_pool());
+  let cache = web::Data::new(BoardCache::from_env());
+  let hub = web::Data::new(Hub::new());
+  revisions::retention();
+  templates::starters();
+  actix_web::rt::spawn(trash::sweep_forever(pool.clone()));
   HttpServer::new(move || {
     App::new()
-      .wrap(Logger::default())
-      .app_data(web::Data::new(create_pool()))
+      .wrap_fn(|req, srv| srv.call(req).map(|res| res.map(aliases::add_location)))
+      .wrap(Compress::default())
+      // the query of /events may carry an access token
+      .wrap(Logger::default().exclude("/events"))
+      .app_data(pool.clone())
+      .app_data(cache.clone())
+      .app_data(hub.clone())
       .configure(cfg_auth)
       .configure(cfg_views)
+      // before cfg_boards, whose POST /boards/{id} would take the batch
+      .configure(cfg_batch)
       .configure(cfg_boards)
+      .configure(cfg_entries)
+      .configure(cfg_crdt)
+      .configure(cfg_edit_keys)
+      .configure(cfg_revisions)
+      .configure(cfg_members)
+      .configure(cfg_transfers)
+      .configure(cfg_notifications)
+      .configure(cfg_shares)
+      .configure(cfg_aliases)
+      .configure(cfg_slugs)
+      .configure(cfg_trash)
+      .configure(cfg_templates)
+      .configure(cfg_listing)
+      .configure(cfg_events)
       .service(hello)
       .wrap(Cors::permissive())
   })
@@ -0,0 +1,261 @@
+use std::fmt;
+use std::time::SystemTime;
+
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, post, web, HttpResponse, Responder, ResponseError};
+use common::{clone, epoch_secs, BoardRole, InvitationDetails, InviteForm, MemberDetails};
+use diesel::prelude::*;
+
+use crate::auth::{AuthdUser, Visitor};
+use crate::boards::{check_access, find_board, Access, BoardNotFound};
+use crate::db::{self, BoardMember, DbPool};
+use crate::events::Hub;
+use crate::schema::{board, board_member, user};
+
+pub fn cfg_members(cfg: &mut web::ServiceConfig) {
+  cfg
+    .service(list_members)
+    .service(invite_member)
+    .service(remove_member)
+    .service(list_invitations)
+    .service(accept_invitation)
+    .service(decline_invitation);
+}
+
+/// Role of a user who accepted an invitation to the board
+pub fn role_of(conn: &mut SqliteConnection, board: i64, user: i64) -> Option<BoardRole> {
+  use crate::schema::board_member::dsl::*;
+  let stored: Option<String> = (board_member.find((board, user)))
+    .filter(accepted.eq(true))
+    .select(role)
+    .first(conn)
+    .optional()
+    .unwrap();
+  stored.as_deref().and_then(BoardRole::parse)
+}
+
+/// Delete every membership and invitation of a board
+pub fn purge(conn: &mut SqliteConnection, board: i64) {
+  use crate::schema::board_member::dsl::*;
+  diesel::delete(board_member.filter(board_id.eq(board))).execute(conn).unwrap();
+}
+
+#[derive(Clone, Debug)]
+pub enum MemberError {
+  Board(BoardNotFound),
+  UnknownUser(String),
+  /// The owner can't also be a member
+  IsOwner,
+  NoInvitation,
+}
+impl From<BoardNotFound> for MemberError {
+  fn from(value: BoardNotFound) -> Self { Self::Board(value) }
+}
+impl fmt::Display for MemberError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Board(e) => write!(f, "{e}"),
+      Self::UnknownUser(name) => write!(f, "There is no user called {name}"),
+      Self::IsOwner => write!(f, "The owner of a board already has every permission"),
+      Self::NoInvitation => write!(f, "There is no pending invitation to this board"),
+    }
+  }
+}
+impl ResponseError for MemberError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Board(e) => e.status_code(),
+      Self::UnknownUser(_) | Self::NoInvitation => StatusCode::NOT_FOUND,
+      Self::IsOwner => StatusCode::CONFLICT,
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Self::Board(e) => e.error_response(),
+      _ => HttpResponse::build(self.status_code()).body(self.to_string()),
+    }
+  }
+}
+
+#[get("/boards/{id}/members")]
+async fn list_members(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let rows = db::read(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    check_access(conn, &current, &Visitor::user(ses_u.id), Access::Manage)?;
+    let rows: Vec<(i64, String, String, bool)> = (board_member::table)
+      .inner_join(user::table.on(board_member::user_id.eq(user::id)))
+      .filter(board_member::board_id.eq(current.id))
+      .order(user::name)
+      .select((board_member::user_id, user::name, board_member::role, board_member::accepted))
+      .load(conn)
+      .unwrap();
+    Ok::<_, BoardNotFound>(rows)
+  })
+  .await?;
+  let members = (rows.into_iter())
+    .filter_map(|(user_id, name, role, accepted)| {
+      Some(MemberDetails { user_id, name, role: BoardRole::parse(&role)?, accepted })
+    })
+    .collect::<Vec<_>>();
+  Ok(HttpResponse::Ok().json(members))
+}
+
+/// Invite a user by name, or change the role of an existing member
+#[post("/boards/{id}/members")]
+async fn invite_member(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+  form: web::Json<InviteForm>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let InviteForm { name, role } = form.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let member = db::write(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    check_access(conn, &current, &Visitor::user(ses_u.id), Access::Manage)?;
+    let invitee: i64 = (user::table.filter(user::name.eq(&name)))
+      .select(user::id)
+      .first(conn)
+      .optional()
+      .unwrap()
+      .ok_or_else(|| MemberError::UnknownUser(name.clone()))?;
+    if Some(invitee) == current.owner_id {
+      return Err(MemberError::IsOwner);
+    }
+    let invitation = BoardMember {
+      board_id: current.id,
+      user_id: invitee,
+      role: role.as_str().to_string(),
+      accepted: false,
+      invited_by: Some(ses_u.id),
+      created_at: now,
+    };
+    // changing the role of a member doesn't require them to accept again
+    diesel::insert_into(board_member::table)
+      .values(&invitation)
+      .on_conflict((board_member::board_id, board_member::user_id))
+      .do_update()
+      .set(board_member::role.eq(&invitation.role))
+      .execute(conn)
+      .unwrap();
+    let accepted = (board_member::table.find((current.id, invitee)))
+      .select(board_member::accepted)
+      .first(conn)
+      .unwrap();
+    Ok(MemberDetails { user_id: invitee, name, role, accepted })
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(member))
+}
+
+/// Revoke a membership or invitation. Members may also remove themselves.
+#[delete("/boards/{id}/members/{user}")]
+async fn remove_member(
+  pool: web::Data<DbPool>,
+  hub: web::Data<Hub>,
+  ses_u: AuthdUser,
+  path: web::Path<(String, i64)>,
+) -> actix_web::Result<impl Responder> {
+  let (target, member) = path.into_inner();
+  let version = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      let current = find_board(conn, &target)?;
+      if member != ses_u.id {
+        check_access(conn, &current, &Visitor::user(ses_u.id), Access::Manage)?;
+      }
+      let count =
+        diesel::delete(board_member::table.find((current.id, member))).execute(conn).unwrap();
+      (0 < count).then_some(current.version).ok_or(MemberError::NoInvitation)
+    }),
+  )
+  .await?;
+  // the layout is the same, but the event streams of the former member learn
+  // that they lost access
+  hub.changed(&target, version);
+  Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/invitations")]
+async fn list_invitations(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+) -> actix_web::Result<impl Responder> {
+  let rows = db::read(&pool, move |conn| {
+    let inviter = diesel::alias!(user as inviter);
+    let rows: Vec<(String, String, String, Option<String>, i64)> = (board_member::table)
+      .inner_join(board::table.on(board_member::board_id.eq(board::id)))
+      .left_join(inviter.on(board_member::invited_by.eq(inviter.field(user::id).nullable())))
+      .filter(board_member::user_id.eq(ses_u.id).and(board_member::accepted.eq(false)))
+      .filter(board::deleted_at.is_null())
+      .order(board_member::created_at.desc())
+      .select((
+        board::url,
+        board::name,
+        board_member::role,
+        inviter.field(user::name).nullable(),
+        board_member::created_at,
+      ))
+      .load(conn)
+      .unwrap();
+    Ok::<_, std::convert::Infallible>(rows)
+  })
+  .await?;
+  let invitations = (rows.into_iter())
+    .filter_map(|(board_url, board_name, role, invited_by, created_at)| {
+      let role = BoardRole::parse(&role)?;
+      Some(InvitationDetails {
+        board_url: board_url.into(),
+        board_name,
+        role,
+        invited_by,
+        created_at,
+      })
+    })
+    .collect::<Vec<_>>();
+  Ok(HttpResponse::Ok().json(invitations))
+}
+
+#[post("/invitations/{id}/accept")]
+async fn accept_invitation(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  db::write(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    let count = diesel::update(board_member::table.find((current.id, ses_u.id)))
+      .filter(board_member::accepted.eq(false))
+      .set(board_member::accepted.eq(true))
+      .execute(conn)
+      .unwrap();
+    (0 < count).then_some(()).ok_or(MemberError::NoInvitation)
+  })
+  .await?;
+  Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/invitations/{id}/decline")]
+async fn decline_invitation(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  db::write(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    let pending =
+      board_member::table.find((current.id, ses_u.id)).filter(board_member::accepted.eq(false));
+    let count = diesel::delete(pending).execute(conn).unwrap();
+    (0 < count).then_some(()).ok_or(MemberError::NoInvitation)
+  })
+  .await?;
+  Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,97 @@
+use std::convert::Infallible;
+
+use common::clone;
+use common::layout::{upgrade_stored, BoardLayout, ViewLayout};
+use diesel::prelude::*;
+
+use crate::db::{self, Board, DbPool};
+
+/// Bring a board that was just read up to the newest layout format, and store
+/// the result so that the upgrade only runs once. The version is left alone
+/// because the content doesn't change, clients upgrade their cached copies the
+/// same way.
+pub async fn upgrade_board(pool: &DbPool, mut board: Board) -> actix_web::Result<Board> {
+  if let Some(new_layout) = upgrade_stored::<BoardLayout>(&board.layout) {
+    board.layout = new_layout.clone();
+    let (board_id, seen_version) = (board.id, board.version);
+    db::write(pool, move |conn| {
+      use crate::schema::board::dsl::*;
+      // if there was a write in the meantime, it already stored the newest format
+      diesel::update(board.filter(id.eq(board_id).and(version.eq(seen_version))))
+        .set(layout.eq(new_layout))
+        .execute(conn)
+        .unwrap();
+      Ok::<_, Infallible>(())
+    })
+    .await?;
+  }
+  Ok(board)
+}
+
+/// Bring a user's view layout that was just read up to the newest format, like
+/// [upgrade_board], writing only if there is something to upgrade
+pub async fn upgrade_read_view(
+  pool: &DbPool,
+  user_id: i64,
+  text: String,
+) -> actix_web::Result<String> {
+  let Some(new_layout) = upgrade_stored::<ViewLayout>(&text) else { return Ok(text) };
+  db::write(
+    pool,
+    clone!(new_layout; move |conn| {
+      use crate::schema::user::dsl::*;
+      // a layout posted in the meantime is already in the newest format
+      diesel::update(user.filter(id.eq(user_id).and(layout.eq(text))))
+        .set(layout.eq(new_layout))
+        .execute(conn)
+        .unwrap();
+      Ok::<_, Infallible>(())
+    }),
+  )
+  .await?;
+  Ok(new_layout)
+}
+
+/// Bring a user's view layout up to the newest format, storing the result
+pub fn upgrade_view(conn: &mut SqliteConnection, user_id: i64, text: String) -> String {
+  use crate::schema::user::dsl::*;
+  match upgrade_stored::<ViewLayout>(&text) {
+    None => text,
+    Some(new_layout) => {
+      diesel::update(user.find(user_id)).set(layout.eq(&new_layout)).execute(conn).unwrap();
+      new_layout
+    },
+  }
+}
+
+/// Upgrade every stored layout in one go, so that old formats can eventually be
+/// dropped. Returns the number of boards and users that were updated.
+pub fn migrate_all(conn: &mut SqliteConnection) -> (usize, usize) {
+  db::atomic(conn, |conn| {
+    let boards: Vec<(i64, String)> = {
+      use crate::schema::board::dsl::*;
+      board.select((id, layout)).load(conn).unwrap()
+    };
+    let mut board_count = 0;
+    for (board_id, text) in boards {
+      if let Some(new_layout) = upgrade_stored::<BoardLayout>(&text) {
+        use crate::schema::board::dsl::*;
+        diesel::update(board.find(board_id)).set(layout.eq(new_layout)).execute(conn).unwrap();
+        board_count += 1;
+      }
+    }
+    let users: Vec<(i64, String)> = {
+      use crate::schema::user::dsl::*;
+      user.select((id, layout)).load(conn).unwrap()
+    };
+    let mut user_count = 0;
+    for (user_id, text) in users {
+      if upgrade_stored::<ViewLayout>(&text).is_some() {
+        upgrade_view(conn, user_id, text);
+        user_count += 1;
+      }
+    }
+    Ok::<_, Infallible>((board_count, user_count))
+  })
+  .unwrap()
+}
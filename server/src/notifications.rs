@@ -0,0 +1,116 @@
+use std::convert::Infallible;
+
+use actix_web::{get, post, web, HttpResponse, Responder};
+use common::{NotificationDetails, NotificationKind};
+use diesel::prelude::*;
+use serde::Deserialize;
+
+use crate::auth::AuthdUser;
+use crate::db::{self, DbPool, Notification};
+use crate::ids;
+use crate::schema::{board, notification, user};
+
+pub fn cfg_notifications(cfg: &mut web::ServiceConfig) {
+  cfg.service(list_notifications).service(mark_read);
+}
+
+/// Most notifications returned at once, newest first
+const PAGE: i64 = 100;
+
+/// Tell `user_id` about something that happened to a board, involving
+/// `other_id` if set
+pub fn notify(
+  conn: &mut SqliteConnection,
+  user_id: i64,
+  kind: NotificationKind,
+  board_id: i64,
+  other_id: Option<i64>,
+  now: i64,
+) {
+  ids::insert_unique(|| {
+    let row = Notification {
+      id: ids::row_id(),
+      user_id,
+      kind: kind.as_str().to_string(),
+      board_id,
+      other_id,
+      read: false,
+      created_at: now,
+    };
+    diesel::insert_into(notification::table).values(row).execute(conn)
+  });
+}
+
+/// Delete the notifications about a board
+pub fn purge(conn: &mut SqliteConnection, board: i64) {
+  use crate::schema::notification::dsl::*;
+  diesel::delete(notification.filter(board_id.eq(board))).execute(conn).unwrap();
+}
+
+#[derive(Deserialize)]
+struct NotificationsQuery {
+  #[serde(default)]
+  unread: bool,
+}
+
+#[get("/notifications")]
+async fn list_notifications(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  query: web::Query<NotificationsQuery>,
+) -> actix_web::Result<impl Responder> {
+  let unread = query.unread;
+  let rows = db::read(&pool, move |conn| {
+    let other = diesel::alias!(user as other);
+    let mut rows = (notification::table)
+      .inner_join(board::table.on(notification::board_id.eq(board::id)))
+      .left_join(other.on(notification::other_id.eq(other.field(user::id).nullable())))
+      .filter(notification::user_id.eq(ses_u.id))
+      .into_boxed();
+    if unread {
+      rows = rows.filter(notification::read.eq(false));
+    }
+    let rows: Vec<(Notification, String, String, Option<String>)> = rows
+      .order(notification::id.desc())
+      .limit(PAGE)
+      .select((
+        Notification::as_select(),
+        board::url,
+        board::name,
+        other.field(user::name).nullable(),
+      ))
+      .load(conn)
+      .unwrap();
+    Ok::<_, Infallible>(rows)
+  })
+  .await?;
+  let notifications = (rows.into_iter())
+    .filter_map(|(row, url, name, other)| {
+      Some(NotificationDetails {
+        id: row.id,
+        kind: NotificationKind::parse(&row.kind)?,
+        board_url: url.into(),
+        board_name: name,
+        other,
+        read: row.read,
+        created_at: row.created_at,
+      })
+    })
+    .collect::<Vec<_>>();
+  Ok(HttpResponse::Ok().json(notifications))
+}
+
+/// Mark every notification of the user as read
+#[post("/notifications/read")]
+async fn mark_read(pool: web::Data<DbPool>, ses_u: AuthdUser) -> actix_web::Result<impl Responder> {
+  db::write(&pool, move |conn| {
+    use crate::schema::notification::dsl::*;
+    (diesel::update(notification.filter(user_id.eq(ses_u.id).and(read.eq(false)))))
+      .set(read.eq(true))
+      .execute(conn)
+      .unwrap();
+    Ok::<_, Infallible>(())
+  })
+  .await?;
+  Ok(HttpResponse::NoContent().finish())
+}
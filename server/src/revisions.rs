@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use std::{env, fmt};
+
+use actix_web::http::header::{ETag, IfMatch};
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, HttpResponse, Responder, ResponseError};
+use common::layout::{BoardLayout, Entry, Item, ItemKind};
+use common::versioned::Versioned;
+use common::{clone, epoch_secs, DiffStatus, ItemDiff, LayoutDiff, RevisionDetails, SectionDiff};
+use diesel::prelude::*;
+
+use crate::auth::Visitor;
+use crate::boards::{
+  canonical_layout, check_access, check_version, commit_layout, find_board, required_tags,
+  version_tag, Access, BoardNotFound, InvalidLayout, WriteError,
+};
+use crate::cache::BoardCache;
+use crate::db::{self, Board, BoardRevision, DbPool};
+use crate::events::Hub;
+use crate::schema::{board_revision, user};
+
+pub fn cfg_revisions(cfg: &mut web::ServiceConfig) {
+  cfg
+    .service(list_revisions)
+    .service(diff_revisions)
+    .service(restore_revision)
+    .service(get_revision);
+}
+
+/// Number of revisions kept per board, read from `REVISION_RETENTION`. Defaults
+/// to 100. Called at startup, so that a bad value stops the server rather than
+/// the first write.
+pub fn retention() -> i64 {
+  static RETENTION: OnceLock<i64> = OnceLock::new();
+  *RETENTION.get_or_init(|| match env::var("REVISION_RETENTION") {
+    Ok(s) =>
+      (s.parse().ok().filter(|n| *n > 0)).expect("REVISION_RETENTION must be a positive integer"),
+    Err(_) => 100,
+  })
+}
+
+/// Store `layout` as `version` of a board and drop the revisions that fall
+/// outside the retention window.
+pub fn record(
+  conn: &mut SqliteConnection,
+  board: i64,
+  version: i32,
+  layout: &str,
+  author: Option<i64>,
+  now: i64,
+) {
+  use crate::schema::board_revision::dsl as r;
+  let rev = BoardRevision {
+    board_id: board,
+    version,
+    layout: layout.to_string(),
+    author_id: author,
+    created_at: now,
+  };
+  diesel::insert_into(r::board_revision).values(rev).execute(conn).unwrap();
+  let cutoff = (r::board_revision.filter(r::board_id.eq(board)))
+    .select(r::version)
+    .order(r::version.desc())
+    .offset(retention())
+    .first::<i32>(conn)
+    .optional()
+    .unwrap();
+  if let Some(cutoff) = cutoff {
+    (diesel::delete(r::board_revision.filter(r::board_id.eq(board).and(r::version.le(cutoff)))))
+      .execute(conn)
+      .unwrap();
+  }
+}
+
+/// Delete the entire history of a board
+pub fn purge(conn: &mut SqliteConnection, board: i64) {
+  use crate::schema::board_revision::dsl as r;
+  diesel::delete(r::board_revision.filter(r::board_id.eq(board))).execute(conn).unwrap();
+}
+
+#[derive(Clone, Debug)]
+pub enum RevisionError {
+  Board(BoardNotFound),
+  NotFound(i32),
+  /// The layout isn't in a format the server understands, so it can't be diffed
+  Opaque(i32),
+}
+impl From<BoardNotFound> for RevisionError {
+  fn from(value: BoardNotFound) -> Self { Self::Board(value) }
+}
+impl fmt::Display for RevisionError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Board(e) => write!(f, "{e}"),
+      Self::NotFound(v) => write!(f, "Version {v} of this board isn't retained"),
+      Self::Opaque(v) => write!(f, "Version {v} of this board isn't a recognized layout"),
+    }
+  }
+}
+impl ResponseError for RevisionError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Board(e) => e.status_code(),
+      Self::NotFound(_) => StatusCode::NOT_FOUND,
+      Self::Opaque(_) => StatusCode::UNPROCESSABLE_ENTITY,
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Self::Board(e) => e.error_response(),
+      _ => HttpResponse::build(self.status_code()).body(self.to_string()),
+    }
+  }
+}
+
+/// The revision holding the layout of `version` of a board. Writes that leave
+/// the layout alone, like renames, bump the version without recording one, so
+/// that's the newest revision up to it.
+pub fn find_revision(
+  conn: &mut SqliteConnection,
+  board: &Board,
+  version: i32,
+) -> Result<BoardRevision, RevisionError> {
+  use crate::schema::board_revision::dsl as r;
+  if board.version < version {
+    return Err(RevisionError::NotFound(version));
+  }
+  (r::board_revision.filter(r::board_id.eq(board.id).and(r::version.le(version))))
+    .order(r::version.desc())
+    .select(BoardRevision::as_select())
+    .first(conn)
+    .optional()
+    .unwrap()
+    .ok_or(RevisionError::NotFound(version))
+}
+
+#[get("/boards/{id}/revisions")]
+async fn list_revisions(
+  pool: web::Data<DbPool>,
+  visitor: Visitor,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let revisions = db::read(&pool, move |conn| {
+    let board = find_board(conn, &target)?;
+    check_access(conn, &board, &visitor, Access::View)?;
+    let rows: Vec<(i32, Option<i64>, Option<String>, i64)> = (board_revision::table)
+      .left_join(user::table.on(board_revision::author_id.eq(user::id.nullable())))
+      .filter(board_revision::board_id.eq(board.id))
+      .order(board_revision::version.desc())
+      .select((
+        board_revision::version,
+        board_revision::author_id,
+        user::name.nullable(),
+        board_revision::created_at,
+      ))
+      .load(conn)
+      .unwrap();
+    Ok::<_, BoardNotFound>(rows)
+  })
+  .await?;
+  let revisions = (revisions.into_iter())
+    .map(|(version, author_id, author_name, created_at)| RevisionDetails {
+      version,
+      author_id,
+      author_name,
+      created_at,
+    })
+    .collect::<Vec<_>>();
+  Ok(HttpResponse::Ok().json(revisions))
+}
+
+#[get("/boards/{id}/revisions/{version}")]
+async fn get_revision(
+  pool: web::Data<DbPool>,
+  visitor: Visitor,
+  path: web::Path<(String, i32)>,
+) -> actix_web::Result<impl Responder> {
+  let (target, version) = path.into_inner();
+  let rev = db::read(&pool, move |conn| {
+    let board = find_board(conn, &target)?;
+    check_access(conn, &board, &visitor, Access::View)?;
+    find_revision(conn, &board, version)
+  })
+  .await?;
+  Ok(HttpResponse::Ok().insert_header(ETag(version_tag(version))).body(rev.layout))
+}
+
+/// A bookmark as it appears in a section
+type Bookmarks = Vec<(String, String)>;
+
+/// What an item points to and the text it shows, the former identifies it in
+/// diffs
+fn item_summary(item: &Item) -> (String, String) {
+  match &item.kind {
+    ItemKind::Link { url, name } => (url.clone(), name.clone()),
+    ItemKind::Note { text } => (format!("note:{}", item.id), text.clone()),
+    ItemKind::Board { url, name } => (format!("board:{url}"), name.clone()),
+    ItemKind::Search { template, name } => (template.clone(), name.clone()),
+  }
+}
+
+/// Flatten a layout into its sections, identified by column and folder path,
+/// with their bookmarks. Items that aren't in a folder form a section with an
+/// empty title.
+fn sections(layout: &str) -> Option<Vec<((String, String), Bookmarks)>> {
+  fn go(
+    column: &str,
+    title: &str,
+    entries: &[Entry],
+    out: &mut Vec<((String, String), Bookmarks)>,
+  ) {
+    let mut items = Vec::new();
+    let mut folders = Vec::new();
+    for entry in entries {
+      match entry {
+        Entry::Item(item) => items.push(item_summary(item)),
+        Entry::Folder(folder) => folders.push(folder),
+        Entry::Separator => (),
+      }
+    }
+    if !items.is_empty() || !title.is_empty() {
+      out.push(((column.to_string(), title.to_string()), items));
+    }
+    for folder in folders {
+      let path = match title {
+        "" => folder.title.clone(),
+        _ => format!("{title}/{}", folder.title),
+      };
+      go(column, &path, &folder.entries, out);
+    }
+  }
+  let BoardLayout::V2(v2) = serde_json::from_str::<BoardLayout>(layout).ok()?.upgrade() else {
+    unreachable!("Layouts are upgraded to the newest version")
+  };
+  let mut sections = Vec::new();
+  for col in &v2.columns {
+    go(&col.title, "", &col.entries, &mut sections);
+  }
+  Some(sections)
+}
+
+fn diff_items(old: &[(String, String)], new: &[(String, String)]) -> Vec<ItemDiff> {
+  let old_map = old.iter().cloned().collect::<HashMap<_, _>>();
+  let new_map = new.iter().cloned().collect::<HashMap<_, _>>();
+  let removed =
+    (old.iter().filter(|(url, _)| !new_map.contains_key(url))).map(|(url, name)| ItemDiff {
+      url: url.clone(),
+      status: DiffStatus::Removed,
+      old_name: Some(name.clone()),
+      new_name: None,
+    });
+  let added_or_changed = new.iter().filter_map(|(url, name)| match old_map.get(url) {
+    None => Some(ItemDiff {
+      url: url.clone(),
+      status: DiffStatus::Added,
+      old_name: None,
+      new_name: Some(name.clone()),
+    }),
+    Some(old_name) if old_name != name => Some(ItemDiff {
+      url: url.clone(),
+      status: DiffStatus::Changed,
+      old_name: Some(old_name.clone()),
+      new_name: Some(name.clone()),
+    }),
+    Some(_) => None,
+  });
+  removed.chain(added_or_changed).collect()
+}
+
+fn diff_layouts(
+  old: &[((String, String), Bookmarks)],
+  new: &[((String, String), Bookmarks)],
+) -> Vec<SectionDiff> {
+  let old_map = old.iter().cloned().collect::<HashMap<_, _>>();
+  let new_map = new.iter().cloned().collect::<HashMap<_, _>>();
+  let section = |(group, title): &(String, String), status, items| SectionDiff {
+    group: group.clone(),
+    title: title.clone(),
+    status,
+    items,
+  };
+  let removed = (old.iter().filter(|(key, _)| !new_map.contains_key(key)))
+    .map(|(key, items)| section(key, DiffStatus::Removed, diff_items(items, &[])));
+  let added_or_changed = new.iter().filter_map(|(key, items)| match old_map.get(key) {
+    None => Some(section(key, DiffStatus::Added, diff_items(&[], items))),
+    Some(old_items) => {
+      let items = diff_items(old_items, items);
+      (!items.is_empty()).then(|| section(key, DiffStatus::Changed, items))
+    },
+  });
+  removed.chain(added_or_changed).collect()
+}
+
+#[get("/boards/{id}/revisions/{from}/diff/{to}")]
+async fn diff_revisions(
+  pool: web::Data<DbPool>,
+  visitor: Visitor,
+  path: web::Path<(String, i32, i32)>,
+) -> actix_web::Result<impl Responder> {
+  let (target, from, to) = path.into_inner();
+  let (old, new) = db::read(&pool, move |conn| {
+    let board = find_board(conn, &target)?;
+    check_access(conn, &board, &visitor, Access::View)?;
+    Ok::<_, RevisionError>((find_revision(conn, &board, from)?, find_revision(conn, &board, to)?))
+  })
+  .await?;
+  let old_sections = sections(&old.layout).ok_or(RevisionError::Opaque(from))?;
+  let new_sections = sections(&new.layout).ok_or(RevisionError::Opaque(to))?;
+  let sections = diff_layouts(&old_sections, &new_sections);
+  Ok(HttpResponse::Ok().json(LayoutDiff { from, to, sections }))
+}
+
+/// Make an old revision the current layout of the board. This creates a new
+/// version, so it can be undone like any other edit.
+#[post("/boards/{id}/revisions/{version}/restore")]
+async fn restore_revision(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  visitor: Visitor,
+  path: web::Path<(String, i32)>,
+  ifmatch: Option<web::Header<IfMatch>>,
+) -> actix_web::Result<impl Responder> {
+  let tags = required_tags(ifmatch)?;
+  let (target, version) = path.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let new_version = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      let current = find_board(conn, &target)?;
+      let author = visitor.user_id;
+      check_access(conn, &current, &visitor, Access::Edit)?;
+      check_version(&current, tags.as_deref(), true)?;
+      let rev = find_revision(conn, &current, version).map_err(RestoreError::Revision)?;
+      // old revisions may predate validation
+      let layout = canonical_layout(&rev.layout).map_err(RestoreError::Invalid)?;
+      Ok::<_, RestoreError>(commit_layout(conn, &current, layout, author, now))
+    }),
+  )
+  .await?;
+  cache.invalidate(&target);
+  hub.changed(&target, new_version);
+  Ok(HttpResponse::NoContent().insert_header(ETag(version_tag(new_version))).finish())
+}
+
+#[derive(Clone, Debug)]
+pub enum RestoreError {
+  Write(WriteError),
+  Revision(RevisionError),
+  Invalid(InvalidLayout),
+}
+impl From<WriteError> for RestoreError {
+  fn from(value: WriteError) -> Self { Self::Write(value) }
+}
+impl From<BoardNotFound> for RestoreError {
+  fn from(value: BoardNotFound) -> Self { Self::Write(value.into()) }
+}
+impl fmt::Display for RestoreError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Write(e) => write!(f, "{e}"),
+      Self::Revision(e) => write!(f, "{e}"),
+      Self::Invalid(e) => write!(f, "{e}"),
+    }
+  }
+}
+impl ResponseError for RestoreError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Write(e) => e.status_code(),
+      Self::Revision(e) => e.status_code(),
+      Self::Invalid(e) => e.status_code(),
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Self::Write(e) => e.error_response(),
+      Self::Revision(e) => e.error_response(),
+      Self::Invalid(e) => e.error_response(),
+    }
+  }
+}
@@ -3,12 +3,91 @@
 diesel::table! {
     board (id) {
         id -> BigInt,
-        url -> BigInt,
+        url -> Text,
         name -> Text,
         version -> Integer,
-        owner_id -> BigInt,
+        owner_id -> Nullable<BigInt>,
         public_mut -> Bool,
         layout -> Text,
+        updated_at -> BigInt,
+        visibility -> Text,
+        slug -> Nullable<Text>,
+        deleted_at -> Nullable<BigInt>,
+        is_template -> Bool,
+        edit_key -> Nullable<Text>,
+        description -> Nullable<Text>,
+        icon -> Nullable<Text>,
+        color -> Nullable<Text>,
+        created_at -> BigInt,
+        updated_by -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    board_alias (url) {
+        url -> Text,
+        board_id -> BigInt,
+        expires_at -> Nullable<BigInt>,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    board_slug (owner_id, slug) {
+        owner_id -> BigInt,
+        slug -> Text,
+        board_id -> BigInt,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    board_crdt (board_id) {
+        board_id -> BigInt,
+        doc -> Binary,
+        updated_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    board_member (board_id, user_id) {
+        board_id -> BigInt,
+        user_id -> BigInt,
+        role -> Text,
+        accepted -> Bool,
+        invited_by -> Nullable<BigInt>,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    board_revision (board_id, version) {
+        board_id -> BigInt,
+        version -> Integer,
+        layout -> Text,
+        author_id -> Nullable<BigInt>,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    board_transfer (board_id) {
+        board_id -> BigInt,
+        from_id -> BigInt,
+        to_id -> BigInt,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    notification (id) {
+        id -> BigInt,
+        user_id -> BigInt,
+        kind -> Text,
+        board_id -> BigInt,
+        other_id -> Nullable<BigInt>,
+        read -> Bool,
+        created_at -> BigInt,
     }
 }
 
@@ -30,4 +109,30 @@ diesel::table! {
     }
 }
 
-diesel::allow_tables_to_appear_in_same_query!(board, session, user,);
+diesel::table! {
+    share_link (token) {
+        token -> Text,
+        board_id -> BigInt,
+        mode -> Text,
+        label -> Nullable<Text>,
+        expires_at -> Nullable<BigInt>,
+        max_uses -> Nullable<Integer>,
+        uses -> Integer,
+        created_by -> BigInt,
+        created_at -> BigInt,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(
+  board,
+  board_alias,
+  board_crdt,
+  board_member,
+  board_revision,
+  board_slug,
+  board_transfer,
+  notification,
+  session,
+  share_link,
+  user,
+);
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::{ready, Ready};
+use std::time::{Duration, SystemTime};
+
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, post, web, FromRequest, HttpResponse, Responder, ResponseError};
+use common::{epoch_secs, NewShareForm, ShareDetails, ShareMode, ShareRedemption};
+use diesel::prelude::*;
+
+use crate::auth::{AuthdUser, Visitor};
+use crate::bearer_token::{make_token, parse_token, TokenError};
+use crate::boards::{check_access, find_board, Access, BoardNotFound};
+use crate::db::{self, DbPool, ShareLink};
+use crate::ids;
+use crate::schema::{board, share_link};
+
+pub fn cfg_shares(cfg: &mut web::ServiceConfig) {
+  cfg.service(list_shares).service(new_share).service(revoke_share).service(redeem_share);
+}
+
+/// Header carrying the grant obtained by redeeming a share link
+pub const SHARE_GRANT: &str = "X-Share-Grant";
+
+/// Grants outlive the redemption so that the link doesn't have to be visited
+/// again, but they are checked against the link on every use, so revoking it
+/// takes effect immediately.
+const GRANT_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+
+/// Proof that the bearer redeemed a share link
+#[derive(Clone, Debug)]
+pub struct ShareGrant {
+  pub token: String,
+  pub board_id: i64,
+}
+impl FromRequest for ShareGrant {
+  type Error = TokenError;
+  type Future = Ready<Result<Self, Self::Error>>;
+  fn from_request(req: &actix_web::HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+    ready((|| {
+      let header = req.headers().get(SHARE_GRANT).ok_or(TokenError::NoAuth)?;
+      let mut grant = parse_token(header.to_str().map_err(|_| TokenError::BadAuth)?)?;
+      if grant.expired() {
+        return Err(TokenError::Expired);
+      }
+      if !grant.claims.get("ty").is_some_and(|s| *s == "share") {
+        return Err(TokenError::BadStdField);
+      }
+      let token = grant.claims.remove("share").ok_or(TokenError::BadStdField)?;
+      let board_id = (grant.claims.get("board_id").and_then(|s| s.parse().ok()))
+        .ok_or(TokenError::BadStdField)?;
+      Ok(ShareGrant { token, board_id })
+    })())
+  }
+}
+
+fn mode_access(mode: ShareMode) -> Access {
+  match mode {
+    ShareMode::Read => Access::View,
+    ShareMode::Edit => Access::Edit,
+  }
+}
+
+/// Access granted by a share link that still exists and hasn't expired
+pub fn access_of(conn: &mut SqliteConnection, board: i64, grant: &ShareGrant) -> Option<Access> {
+  use crate::schema::share_link::dsl::*;
+  if grant.board_id != board {
+    return None;
+  }
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let stored: String = (share_link.find(&grant.token))
+    .filter(board_id.eq(board).and(expires_at.is_null().or(expires_at.gt(now))))
+    .select(mode)
+    .first(conn)
+    .optional()
+    .unwrap()?;
+  ShareMode::parse(&stored).map(mode_access)
+}
+
+/// Delete every share link of a board
+pub fn purge(conn: &mut SqliteConnection, board: i64) {
+  use crate::schema::share_link::dsl::*;
+  diesel::delete(share_link.filter(board_id.eq(board))).execute(conn).unwrap();
+}
+
+#[derive(Clone, Debug)]
+pub enum ShareError {
+  Board(BoardNotFound),
+  NotFound,
+  /// The link expired, was used up, or its board was deleted
+  Gone,
+}
+impl From<BoardNotFound> for ShareError {
+  fn from(value: BoardNotFound) -> Self { Self::Board(value) }
+}
+impl fmt::Display for ShareError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Board(e) => write!(f, "{e}"),
+      Self::NotFound => write!(f, "Share link revoked or never existed"),
+      Self::Gone => write!(f, "Share link expired or used up"),
+    }
+  }
+}
+impl ResponseError for ShareError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Board(e) => e.status_code(),
+      Self::NotFound => StatusCode::NOT_FOUND,
+      Self::Gone => StatusCode::GONE,
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Self::Board(e) => e.error_response(),
+      _ => HttpResponse::build(self.status_code()).body(self.to_string()),
+    }
+  }
+}
+
+fn details(link: ShareLink) -> Option<ShareDetails> {
+  Some(ShareDetails {
+    mode: ShareMode::parse(&link.mode)?,
+    token: link.token,
+    label: link.label,
+    expires_at: link.expires_at,
+    max_uses: link.max_uses,
+    uses: link.uses,
+    created_at: link.created_at,
+  })
+}
+
+#[get("/boards/{id}/shares")]
+async fn list_shares(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let links = db::read(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    check_access(conn, &current, &Visitor::user(ses_u.id), Access::Manage)?;
+    let links = (share_link::table.filter(share_link::board_id.eq(current.id)))
+      .order(share_link::created_at.desc())
+      .select(ShareLink::as_select())
+      .load(conn)
+      .unwrap();
+    Ok::<_, BoardNotFound>(links)
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(links.into_iter().filter_map(details).collect::<Vec<_>>()))
+}
+
+#[post("/boards/{id}/shares")]
+async fn new_share(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+  form: web::Json<NewShareForm>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let NewShareForm { mode, label, expires_at, max_uses } = form.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let link = db::write(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    check_access(conn, &current, &Visitor::user(ses_u.id), Access::Manage)?;
+    Ok::<_, BoardNotFound>(ids::insert_unique(|| {
+      let link = ShareLink {
+        token: ids::secret(),
+        board_id: current.id,
+        mode: mode.as_str().to_string(),
+        label: label.clone(),
+        expires_at,
+        max_uses,
+        uses: 0,
+        created_by: ses_u.id,
+        created_at: now,
+      };
+      diesel::insert_into(share_link::table).values(&link).execute(conn)?;
+      Ok(link)
+    }))
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(details(link)))
+}
+
+#[delete("/boards/{id}/shares/{token}")]
+async fn revoke_share(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  path: web::Path<(String, String)>,
+) -> actix_web::Result<impl Responder> {
+  let (target, token) = path.into_inner();
+  db::write(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    check_access(conn, &current, &Visitor::user(ses_u.id), Access::Manage)?;
+    let link = share_link::table.find(&token).filter(share_link::board_id.eq(current.id));
+    let count = diesel::delete(link).execute(conn).unwrap();
+    (0 < count).then_some(()).ok_or(ShareError::NotFound)
+  })
+  .await?;
+  Ok(HttpResponse::NoContent().finish())
+}
+
+/// Exchange a share link for a grant, counting it as one use of the link. No
+/// account is needed.
+#[post("/shares/{token}/redeem")]
+async fn redeem_share(
+  pool: web::Data<DbPool>,
+  token: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let token = token.into_inner();
+  let now = SystemTime::now();
+  let now_secs = epoch_secs(now) as i64;
+  let (link, board_url) = db::write(&pool, move |conn| {
+    let link = (share_link::table.find(&token))
+      .select(ShareLink::as_select())
+      .first(conn)
+      .optional()
+      .unwrap()
+      .ok_or(ShareError::NotFound)?;
+    let expired = link.expires_at.is_some_and(|exp| exp <= now_secs);
+    let used_up = link.max_uses.is_some_and(|max| max <= link.uses);
+    if expired || used_up {
+      return Err(ShareError::Gone);
+    }
+    let board_url: String = (board::table.find(link.board_id))
+      .filter(board::deleted_at.is_null())
+      .select(board::url)
+      .first(conn)
+      .optional()
+      .unwrap()
+      .ok_or(ShareError::Gone)?;
+    diesel::update(share_link::table.find(&token))
+      .set(share_link::uses.eq(share_link::uses + 1))
+      .execute(conn)
+      .unwrap();
+    Ok((link, board_url))
+  })
+  .await?;
+  let mode = ShareMode::parse(&link.mode).ok_or(ShareError::NotFound)?;
+  let lifetime = match link.expires_at {
+    Some(exp) => GRANT_LIFETIME.min(Duration::from_secs((exp - now_secs) as u64)),
+    None => GRANT_LIFETIME,
+  };
+  let grant = make_token(
+    now,
+    lifetime,
+    HashMap::from([
+      ("ty".to_string(), "share".to_string()),
+      ("share".to_string(), link.token),
+      ("board_id".to_string(), link.board_id.to_string()),
+    ]),
+  );
+  Ok(HttpResponse::Ok().json(ShareRedemption {
+    board_url: board_url.into(),
+    mode,
+    grant,
+    expires_at: epoch_secs(now + lifetime) as i64,
+  }))
+}
@@ -0,0 +1,292 @@
+use std::fmt;
+use std::time::SystemTime;
+
+use actix_web::http::header::{ETag, IfModifiedSince, IfNoneMatch};
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, post, web, HttpResponse, Responder, ResponseError};
+use common::{clone, epoch_secs, is_valid_slug, SlugDetails, SlugForm, MAX_SLUG_LEN};
+use diesel::prelude::*;
+
+use crate::auth::{AuthdUser, Visitor};
+use crate::boards::{
+  check_access, details_response, find_board, layout_response, load_board, version_tag, Access,
+  BoardNotFound, Route,
+};
+use crate::cache::BoardCache;
+use crate::db::{self, Board, BoardSlug, DbPool};
+use crate::events::Hub;
+use crate::schema::{board, board_slug, user};
+
+pub fn cfg_slugs(cfg: &mut web::ServiceConfig) {
+  cfg
+    .service(claim_slug)
+    .service(clear_slug)
+    .service(list_slugs)
+    .service(release_slug)
+    .service(get_board_by_slug)
+    .service(get_board_layout_by_slug);
+}
+
+/// Url of the board that holds `slug` in the namespace of the user called
+/// `owner`, whether it's the board's current slug or a former one
+pub fn resolve(conn: &mut SqliteConnection, owner: &str, slug: &str) -> Option<String> {
+  (board_slug::table)
+    .inner_join(user::table.on(board_slug::owner_id.eq(user::id)))
+    .inner_join(board::table.on(board_slug::board_id.eq(board::id)))
+    .filter(user::name.eq(owner).and(board_slug::slug.eq(slug)))
+    .filter(board::owner_id.eq(board_slug::owner_id.nullable()))
+    .select(board::url)
+    .first(conn)
+    .optional()
+    .unwrap()
+}
+
+/// Release every slug of a board
+pub fn purge(conn: &mut SqliteConnection, board: i64) {
+  use crate::schema::board_slug::dsl::*;
+  diesel::delete(board_slug.filter(board_id.eq(board))).execute(conn).unwrap();
+}
+
+#[derive(Clone, Debug)]
+pub enum SlugError {
+  Board(BoardNotFound),
+  Invalid,
+  /// Reserved by another board of the same owner
+  Taken(String),
+  NotFound,
+}
+impl From<BoardNotFound> for SlugError {
+  fn from(value: BoardNotFound) -> Self { Self::Board(value) }
+}
+impl fmt::Display for SlugError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Board(e) => write!(f, "{e}"),
+      Self::Invalid => write!(
+        f,
+        "Slugs are 1 to {MAX_SLUG_LEN} lowercase letters, digits and dashes, not starting or \
+         ending with a dash"
+      ),
+      Self::Taken(slug) => write!(f, "Another of your boards already uses the slug {slug}"),
+      Self::NotFound => write!(f, "No board has this slug"),
+    }
+  }
+}
+impl ResponseError for SlugError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Board(e) => e.status_code(),
+      Self::Invalid => StatusCode::UNPROCESSABLE_ENTITY,
+      Self::Taken(_) => StatusCode::CONFLICT,
+      Self::NotFound => StatusCode::NOT_FOUND,
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Self::Board(e) => e.error_response(),
+      _ => HttpResponse::build(self.status_code()).body(self.to_string()),
+    }
+  }
+}
+
+/// Change the current slug of a board. Like other changes to its details, this
+/// makes a new version.
+fn set_current(
+  conn: &mut SqliteConnection,
+  current: &Board,
+  new_slug: Option<&str>,
+  by: i64,
+  now: i64,
+) {
+  use crate::schema::board::dsl::*;
+  diesel::update(board.find(current.id))
+    .set((slug.eq(new_slug), version.eq(version + 1), updated_at.eq(now), updated_by.eq(by)))
+    .execute(conn)
+    .unwrap();
+}
+
+/// Give a board a slug, keeping its former slug reserved for it
+#[post("/boards/{id}/slug")]
+async fn claim_slug(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+  form: web::Json<SlugForm>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let SlugForm { slug } = form.into_inner();
+  if !is_valid_slug(&slug) {
+    return Err(SlugError::Invalid.into());
+  }
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let new_version = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      let current = find_board(conn, &target)?;
+      check_access(conn, &current, &Visitor::user(ses_u.id), Access::Own)?;
+      let holder: Option<i64> = (board_slug::table.find((ses_u.id, &slug)))
+        .select(board_slug::board_id)
+        .first(conn)
+        .optional()
+        .unwrap();
+      match holder {
+        Some(holder) if holder != current.id => return Err(SlugError::Taken(slug)),
+        Some(_) => {},
+        None => {
+          let reservation = BoardSlug {
+            owner_id: ses_u.id,
+            slug: slug.clone(),
+            board_id: current.id,
+            created_at: now,
+          };
+          diesel::insert_into(board_slug::table).values(reservation).execute(conn).unwrap();
+        },
+      }
+      set_current(conn, &current, Some(&slug), ses_u.id, now);
+      Ok(current.version + 1)
+    }),
+  )
+  .await?;
+  cache.invalidate(&target);
+  hub.changed(&target, new_version);
+  Ok(HttpResponse::NoContent().insert_header(ETag(version_tag(new_version))).finish())
+}
+
+/// Stop showing a slug for the board. It stays reserved and keeps resolving.
+#[delete("/boards/{id}/slug")]
+async fn clear_slug(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let new_version = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      let current = find_board(conn, &target)?;
+      check_access(conn, &current, &Visitor::user(ses_u.id), Access::Own)?;
+      if current.slug.is_none() {
+        return Err(SlugError::NotFound);
+      }
+      set_current(conn, &current, None, ses_u.id, now);
+      Ok(current.version + 1)
+    }),
+  )
+  .await?;
+  cache.invalidate(&target);
+  hub.changed(&target, new_version);
+  Ok(HttpResponse::NoContent().insert_header(ETag(version_tag(new_version))).finish())
+}
+
+#[get("/boards/{id}/slugs")]
+async fn list_slugs(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let (current, slugs) = db::read(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    check_access(conn, &current, &Visitor::user(ses_u.id), Access::Own)?;
+    let slugs = (board_slug::table.filter(board_slug::board_id.eq(current.id)))
+      .order(board_slug::created_at.desc())
+      .select(BoardSlug::as_select())
+      .load(conn)
+      .unwrap();
+    Ok::<_, BoardNotFound>((current, slugs))
+  })
+  .await?;
+  let slugs = (slugs.into_iter())
+    .map(|s| SlugDetails {
+      current: current.slug.as_ref() == Some(&s.slug),
+      slug: s.slug,
+      created_at: s.created_at,
+    })
+    .collect::<Vec<_>>();
+  Ok(HttpResponse::Ok().json(slugs))
+}
+
+/// Free a slug for other boards of the owner
+#[delete("/boards/{id}/slugs/{slug}")]
+async fn release_slug(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  ses_u: AuthdUser,
+  path: web::Path<(String, String)>,
+) -> actix_web::Result<impl Responder> {
+  let (target, slug) = path.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let new_version = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      let current = find_board(conn, &target)?;
+      check_access(conn, &current, &Visitor::user(ses_u.id), Access::Own)?;
+      let reservation = (board_slug::table.find((ses_u.id, &slug)))
+        .filter(board_slug::board_id.eq(current.id));
+      let count = diesel::delete(reservation).execute(conn).unwrap();
+      if count == 0 {
+        return Err(SlugError::NotFound);
+      }
+      // only releasing the current slug changes the board
+      if current.slug.as_ref() != Some(&slug) {
+        return Ok(None);
+      }
+      set_current(conn, &current, None, ses_u.id, now);
+      Ok(Some(current.version + 1))
+    }),
+  )
+  .await?;
+  cache.invalidate(&target);
+  if let Some(new_version) = new_version {
+    hub.changed(&target, new_version);
+  }
+  Ok(HttpResponse::NoContent().finish())
+}
+
+async fn load_by_slug(
+  pool: &DbPool,
+  cache: &BoardCache,
+  owner: String,
+  slug: String,
+  visitor: &Visitor,
+) -> actix_web::Result<std::sync::Arc<Board>> {
+  let url =
+    db::read(pool, move |conn| resolve(conn, &owner, &slug).ok_or(SlugError::NotFound)).await?;
+  load_board(pool, cache, &url, visitor).await
+}
+
+#[get("/b/{user}/{slug}")]
+async fn get_board_by_slug(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  visitor: Visitor,
+  path: web::Path<(String, String)>,
+  ifnmatch: Option<web::Header<IfNoneMatch>>,
+  ifmodsince: Option<web::Header<IfModifiedSince>>,
+) -> actix_web::Result<HttpResponse> {
+  let (owner, slug) = path.into_inner();
+  let board = load_by_slug(&pool, &cache, owner, slug, &visitor).await?;
+  let (ifnmatch, ifmodsince) = (ifnmatch.as_deref(), ifmodsince.as_deref());
+  details_response(&pool, &board, &visitor, Route::Slug, ifnmatch, ifmodsince).await
+}
+
+#[get("/b/{user}/{slug}/layout")]
+async fn get_board_layout_by_slug(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  visitor: Visitor,
+  path: web::Path<(String, String)>,
+  ifnmatch: Option<web::Header<IfNoneMatch>>,
+  ifmodsince: Option<web::Header<IfModifiedSince>>,
+) -> actix_web::Result<HttpResponse> {
+  let (owner, slug) = path.into_inner();
+  let board = load_by_slug(&pool, &cache, owner, slug, &visitor).await?;
+  let (ifnmatch, ifmodsince) = (ifnmatch.as_deref(), ifmodsince.as_deref());
+  layout_response(&pool, &board, &visitor, Route::Slug, ifnmatch, ifmodsince).await
+}
@@ -0,0 +1,75 @@
+use std::convert::Infallible;
+use std::sync::OnceLock;
+
+use actix_web::{get, web, HttpResponse, Responder};
+use common::layout::BoardLayout;
+use common::versioned::Versioned;
+use common::{StarterTemplate, TemplateBoard, Templates};
+use diesel::prelude::*;
+use serde::Deserialize;
+
+use crate::auth::Visitor;
+use crate::db::{self, DbPool};
+
+pub fn cfg_templates(cfg: &mut web::ServiceConfig) { cfg.service(list_templates); }
+
+/// Starter templates shipped with the server, keyed by file name
+const STARTERS: &[(&str, &str)] = &[
+  ("blank", include_str!("../templates/blank.json")),
+  ("search", include_str!("../templates/search.json")),
+  ("developer", include_str!("../templates/developer.json")),
+];
+
+#[derive(Deserialize)]
+struct StarterFile {
+  name: String,
+  description: String,
+  layout: BoardLayout,
+}
+
+/// Starter templates, checked like any layout a client would post. Called at
+/// startup, so that a broken file stops the server rather than every request
+/// for the templates.
+pub fn starters() -> &'static [StarterTemplate] {
+  static STARTER_TEMPLATES: OnceLock<Vec<StarterTemplate>> = OnceLock::new();
+  STARTER_TEMPLATES.get_or_init(|| {
+    (STARTERS.iter())
+      .map(|(key, text)| {
+        let file = serde_json::from_str::<StarterFile>(text)
+          .unwrap_or_else(|e| panic!("Starter template {key} is malformed: {e}"));
+        let layout = file.layout.upgrade();
+        let errors = layout.validate();
+        assert!(errors.is_empty(), "Starter template {key} is invalid: {errors:?}");
+        StarterTemplate {
+          key: key.to_string(),
+          name: file.name,
+          description: file.description,
+          layout,
+        }
+      })
+      .collect()
+  })
+}
+
+/// Starter templates, and the boards the user marked as templates
+#[get("/templates")]
+async fn list_templates(
+  pool: web::Data<DbPool>,
+  visitor: Visitor,
+) -> actix_web::Result<impl Responder> {
+  let boards = match visitor.user_id {
+    None => Vec::new(),
+    Some(user_id) =>
+      db::read(&pool, move |conn| {
+        use crate::schema::board::dsl::*;
+        let own = board.filter(owner_id.eq(user_id).and(deleted_at.is_null()));
+        let rows: Vec<(String, String)> =
+          own.filter(is_template.eq(true)).order(name).select((url, name)).load(conn).unwrap();
+        Ok::<_, Infallible>(rows)
+      })
+      .await?,
+  };
+  let boards =
+    boards.into_iter().map(|(url, name)| TemplateBoard { url: url.into(), name }).collect();
+  Ok(HttpResponse::Ok().json(Templates { starters: starters().to_vec(), boards }))
+}
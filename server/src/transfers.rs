@@ -0,0 +1,258 @@
+//! Handing a board over to another user. The owner offers it, and it only
+//! changes hands once the recipient accepts. Both sides are notified along the
+//! way, see [crate::notifications].
+
+use std::fmt;
+use std::time::SystemTime;
+
+use actix_web::http::header::ETag;
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, post, web, HttpResponse, Responder, ResponseError};
+use common::{clone, epoch_secs, NotificationKind, TransferDetails, TransferForm};
+use diesel::prelude::*;
+
+use crate::auth::{AuthdUser, Visitor};
+use crate::boards::{check_access, find_board, version_tag, Access, BoardNotFound};
+use crate::cache::BoardCache;
+use crate::db::{self, BoardTransfer, DbPool};
+use crate::events::Hub;
+use crate::notifications::notify;
+use crate::schema::{board, board_member, board_transfer, user};
+use crate::slugs;
+
+pub fn cfg_transfers(cfg: &mut web::ServiceConfig) {
+  cfg
+    .service(offer_board)
+    .service(cancel_transfer)
+    .service(list_transfers)
+    .service(accept_transfer)
+    .service(decline_transfer);
+}
+
+/// Drop the pending transfer of a board
+pub fn purge(conn: &mut SqliteConnection, board: i64) {
+  use crate::schema::board_transfer::dsl::*;
+  diesel::delete(board_transfer.find(board)).execute(conn).unwrap();
+}
+
+#[derive(Clone, Debug)]
+pub enum TransferError {
+  Board(BoardNotFound),
+  UnknownUser(String),
+  ToSelf,
+  /// The board is already being offered to someone
+  Pending,
+  NoTransfer,
+}
+impl From<BoardNotFound> for TransferError {
+  fn from(value: BoardNotFound) -> Self { Self::Board(value) }
+}
+impl fmt::Display for TransferError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Board(e) => write!(f, "{e}"),
+      Self::UnknownUser(name) => write!(f, "There is no user called {name}"),
+      Self::ToSelf => write!(f, "You already own this board"),
+      Self::Pending => write!(f, "The board is already offered to someone, cancel that first"),
+      Self::NoTransfer => write!(f, "There is no pending transfer of this board"),
+    }
+  }
+}
+impl ResponseError for TransferError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Board(e) => e.status_code(),
+      Self::UnknownUser(_) | Self::NoTransfer => StatusCode::NOT_FOUND,
+      Self::ToSelf => StatusCode::UNPROCESSABLE_ENTITY,
+      Self::Pending => StatusCode::CONFLICT,
+    }
+  }
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Self::Board(e) => e.error_response(),
+      _ => HttpResponse::build(self.status_code()).body(self.to_string()),
+    }
+  }
+}
+
+/// Offer a board to another user
+#[post("/boards/{id}/transfer")]
+async fn offer_board(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+  form: web::Json<TransferForm>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let TransferForm { name } = form.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let details = db::write(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    check_access(conn, &current, &Visitor::user(ses_u.id), Access::Own)?;
+    let recipient: i64 = (user::table.filter(user::name.eq(&name)))
+      .select(user::id)
+      .first(conn)
+      .optional()
+      .unwrap()
+      .ok_or_else(|| TransferError::UnknownUser(name.clone()))?;
+    if recipient == ses_u.id {
+      return Err(TransferError::ToSelf);
+    }
+    let offer =
+      BoardTransfer { board_id: current.id, from_id: ses_u.id, to_id: recipient, created_at: now };
+    let inserted = (diesel::insert_into(board_transfer::table).values(offer))
+      .on_conflict_do_nothing()
+      .execute(conn)
+      .unwrap();
+    if inserted == 0 {
+      return Err(TransferError::Pending);
+    }
+    notify(conn, recipient, NotificationKind::Requested, current.id, Some(ses_u.id), now);
+    Ok(TransferDetails {
+      board_url: current.url.into(),
+      board_name: current.name,
+      from: ses_u.name,
+      to: name,
+      created_at: now,
+    })
+  })
+  .await?;
+  Ok(HttpResponse::Created().json(details))
+}
+
+/// Withdraw the offer of a board
+#[delete("/boards/{id}/transfer")]
+async fn cancel_transfer(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  db::write(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    check_access(conn, &current, &Visitor::user(ses_u.id), Access::Own)?;
+    let offer = pending(conn, current.id)?;
+    purge(conn, current.id);
+    notify(conn, offer.to_id, NotificationKind::Cancelled, current.id, Some(ses_u.id), now);
+    Ok::<_, TransferError>(())
+  })
+  .await?;
+  Ok(HttpResponse::NoContent().finish())
+}
+
+fn pending(conn: &mut SqliteConnection, board: i64) -> Result<BoardTransfer, TransferError> {
+  (board_transfer::table.find(board))
+    .select(BoardTransfer::as_select())
+    .first(conn)
+    .optional()
+    .unwrap()
+    .ok_or(TransferError::NoTransfer)
+}
+
+/// The transfers the user offered or was offered
+#[get("/transfers")]
+async fn list_transfers(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+) -> actix_web::Result<impl Responder> {
+  let rows = db::read(&pool, move |conn| {
+    let (from, to) = diesel::alias!(user as from_user, user as to_user);
+    let rows: Vec<(String, String, String, String, i64)> = (board_transfer::table)
+      .inner_join(board::table.on(board_transfer::board_id.eq(board::id)))
+      .inner_join(from.on(board_transfer::from_id.eq(from.field(user::id))))
+      .inner_join(to.on(board_transfer::to_id.eq(to.field(user::id))))
+      .filter(board_transfer::from_id.eq(ses_u.id).or(board_transfer::to_id.eq(ses_u.id)))
+      .filter(board::deleted_at.is_null())
+      .order(board_transfer::created_at.desc())
+      .select((
+        board::url,
+        board::name,
+        from.field(user::name),
+        to.field(user::name),
+        board_transfer::created_at,
+      ))
+      .load(conn)
+      .unwrap();
+    Ok::<_, BoardNotFound>(rows)
+  })
+  .await?;
+  let transfers = (rows.into_iter())
+    .map(|(url, name, from, to, created_at)| TransferDetails {
+      board_url: url.into(),
+      board_name: name,
+      from,
+      to,
+      created_at,
+    })
+    .collect::<Vec<_>>();
+  Ok(HttpResponse::Ok().json(transfers))
+}
+
+/// Take ownership of a board offered to the user. Its slugs are dropped, since
+/// they belong to the namespace of the previous owner, and a membership of the
+/// new owner is dropped since they have every permission.
+#[post("/transfers/{id}/accept")]
+async fn accept_transfer(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  let new_version = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      use crate::schema::board::dsl::*;
+      let current = find_board(conn, &target)?;
+      let offer = pending(conn, current.id)?;
+      if offer.to_id != ses_u.id {
+        return Err(TransferError::NoTransfer);
+      }
+      purge(conn, current.id);
+      slugs::purge(conn, current.id);
+      diesel::delete(board_member::table.find((current.id, ses_u.id))).execute(conn).unwrap();
+      diesel::update(board.find(current.id))
+        .set((
+          owner_id.eq(ses_u.id),
+          slug.eq(None::<String>),
+          version.eq(version + 1),
+          updated_at.eq(now),
+          updated_by.eq(ses_u.id),
+        ))
+        .execute(conn)
+        .unwrap();
+      let kind = NotificationKind::Accepted;
+      notify(conn, offer.from_id, kind, current.id, Some(ses_u.id), now);
+      Ok(current.version + 1)
+    }),
+  )
+  .await?;
+  cache.invalidate(&target);
+  hub.changed(&target, new_version);
+  Ok(HttpResponse::NoContent().insert_header(ETag(version_tag(new_version))).finish())
+}
+
+#[post("/transfers/{id}/decline")]
+async fn decline_transfer(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let now = epoch_secs(SystemTime::now()) as i64;
+  db::write(&pool, move |conn| {
+    let current = find_board(conn, &target)?;
+    let offer = pending(conn, current.id)?;
+    if offer.to_id != ses_u.id {
+      return Err(TransferError::NoTransfer);
+    }
+    purge(conn, current.id);
+    notify(conn, offer.from_id, NotificationKind::Declined, current.id, Some(ses_u.id), now);
+    Ok(())
+  })
+  .await?;
+  Ok(HttpResponse::NoContent().finish())
+}
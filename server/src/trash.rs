@@ -0,0 +1,162 @@
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+use std::{env, fmt};
+
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, post, web, HttpResponse, Responder, ResponseError};
+use common::{clone, epoch_secs, TrashedBoard};
+use diesel::prelude::*;
+
+use crate::auth::AuthdUser;
+use crate::cache::BoardCache;
+use crate::db::{self, Board, DbPool};
+use crate::events::Hub;
+use crate::{aliases, crdt, members, notifications, revisions, schema, shares, slugs, transfers};
+
+pub fn cfg_trash(cfg: &mut web::ServiceConfig) {
+  cfg.service(list_trash).service(restore_board).service(purge_board);
+}
+
+/// Seconds a deleted board stays in the trash, read from
+/// `BOARD_TRASH_RETENTION`. Defaults to 30 days, 0 means forever.
+fn retention() -> Option<i64> {
+  static RETENTION: OnceLock<i64> = OnceLock::new();
+  let retention = *RETENTION.get_or_init(|| match env::var("BOARD_TRASH_RETENTION") {
+    Ok(s) => s.parse().expect("BOARD_TRASH_RETENTION must be a number of seconds"),
+    Err(_) => 60 * 60 * 24 * 30,
+  });
+  (0 < retention).then_some(retention)
+}
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Delete a board for good, along with everything that refers to it
+pub fn purge(conn: &mut SqliteConnection, board_id: i64) {
+  diesel::delete(schema::board::table.find(board_id)).execute(conn).unwrap();
+  revisions::purge(conn, board_id);
+  members::purge(conn, board_id);
+  shares::purge(conn, board_id);
+  aliases::purge(conn, board_id);
+  slugs::purge(conn, board_id);
+  crdt::purge(conn, board_id);
+  transfers::purge(conn, board_id);
+  notifications::purge(conn, board_id);
+}
+
+/// Purge the boards that have been in the trash for longer than the
+/// retention, returning how many there were
+pub fn sweep(conn: &mut SqliteConnection, now: i64) -> usize {
+  use crate::schema::board::dsl::*;
+  let Some(retention) = retention() else {
+    return 0;
+  };
+  let expired: Vec<i64> =
+    board.filter(deleted_at.le(now - retention)).select(id).load(conn).unwrap();
+  for board_id in &expired {
+    purge(conn, *board_id);
+  }
+  expired.len()
+}
+
+/// Run [sweep] periodically for the lifetime of the server
+pub async fn sweep_forever(pool: web::Data<DbPool>) {
+  let mut interval = actix_web::rt::time::interval(SWEEP_INTERVAL);
+  loop {
+    interval.tick().await;
+    let now = epoch_secs(SystemTime::now()) as i64;
+    match db::write(&pool, move |conn| Ok::<_, std::convert::Infallible>(sweep(conn, now))).await {
+      Ok(0) => {},
+      Ok(count) => eprintln!("Purged {count} boards from the trash"),
+      Err(e) => eprintln!("Failed to sweep the trash: {e}"),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct NotInTrash;
+impl fmt::Display for NotInTrash {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "There is no such board in your trash")
+  }
+}
+impl ResponseError for NotInTrash {
+  fn status_code(&self) -> StatusCode { StatusCode::NOT_FOUND }
+}
+
+/// Trashed board of the user with this url
+fn find_trashed(conn: &mut SqliteConnection, user: i64, target: &str) -> Result<Board, NotInTrash> {
+  use crate::schema::board::dsl::*;
+  (board.filter(url.eq(target).and(owner_id.eq(user)).and(deleted_at.is_not_null())))
+    .select(Board::as_select())
+    .first(conn)
+    .optional()
+    .unwrap()
+    .ok_or(NotInTrash)
+}
+
+#[get("/trash")]
+async fn list_trash(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+) -> actix_web::Result<impl Responder> {
+  let trashed = db::read(&pool, move |conn| {
+    use crate::schema::board::dsl::*;
+    let trashed: Vec<(i64, String, String, Option<i64>)> = (board
+      .filter(owner_id.eq(ses_u.id).and(deleted_at.is_not_null())))
+    .order(deleted_at.desc())
+    .select((id, url, name, deleted_at))
+    .load(conn)
+    .unwrap();
+    Ok::<_, std::convert::Infallible>(trashed)
+  })
+  .await?;
+  let trashed = (trashed.into_iter())
+    .filter_map(|(id, url, name, deleted_at)| {
+      let deleted_at = deleted_at?;
+      let purge_at = retention().map(|r| deleted_at + r);
+      Some(TrashedBoard { id, url: url.into(), name, deleted_at, purge_at })
+    })
+    .collect::<Vec<_>>();
+  Ok(HttpResponse::Ok().json(trashed))
+}
+
+#[post("/trash/{id}/restore")]
+async fn restore_board(
+  pool: web::Data<DbPool>,
+  cache: web::Data<BoardCache>,
+  hub: web::Data<Hub>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  let restored_version = db::write(
+    &pool,
+    clone!(target; move |conn| {
+      use crate::schema::board::dsl::*;
+      let trashed = find_trashed(conn, ses_u.id, &target)?;
+      diesel::update(board.find(trashed.id)).set(deleted_at.eq(None::<i64>)).execute(conn).unwrap();
+      Ok::<_, NotInTrash>(trashed.version)
+    }),
+  )
+  .await?;
+  cache.invalidate(&target);
+  hub.changed(&target, restored_version);
+  Ok(HttpResponse::NoContent().finish())
+}
+
+/// Empty a board from the trash without waiting for the retention
+#[delete("/trash/{id}")]
+async fn purge_board(
+  pool: web::Data<DbPool>,
+  ses_u: AuthdUser,
+  target_board: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+  let target = target_board.into_inner();
+  db::write(&pool, move |conn| {
+    let trashed = find_trashed(conn, ses_u.id, &target)?;
+    purge(conn, trashed.id);
+    Ok::<_, NotInTrash>(())
+  })
+  .await?;
+  Ok(HttpResponse::NoContent().finish())
+}
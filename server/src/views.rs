@@ -1,8 +1,12 @@
+use std::convert::Infallible;
+
 use actix_web::{get, post, web, HttpResponse, Responder};
 use diesel::prelude::*;
 
 use crate::auth::AuthdUser;
-use crate::db::DbPool;
+use crate::db::{self, DbPool};
+use crate::events::Hub;
+use crate::migrate;
 
 pub fn cfg_views(cfg: &mut web::ServiceConfig) {
   cfg.service(get_layout).service(post_layout).service(own_boards);
@@ -13,28 +17,29 @@ pub async fn get_layout(
   pool: web::Data<DbPool>,
   ses_u: AuthdUser,
 ) -> actix_web::Result<impl Responder> {
-  let layout: String = web::block(move || {
+  let stored: String = db::read(&pool, move |conn| {
     use crate::schema::user::dsl::*;
-    user.find(ses_u.id).select(layout).first(&mut pool.get().unwrap()).unwrap()
+    Ok::<_, Infallible>(user.find(ses_u.id).select(layout).first(conn).unwrap())
   })
   .await?;
+  let layout = migrate::upgrade_read_view(&pool, ses_u.id, stored).await?;
   Ok(HttpResponse::Ok().body(layout))
 }
 
 #[post("/layout")]
 pub async fn post_layout(
   pool: web::Data<DbPool>,
+  hub: web::Data<Hub>,
   ses_u: AuthdUser,
   body: String,
 ) -> actix_web::Result<impl Responder> {
-  web::block(move || {
+  db::write(&pool, move |conn| {
     use crate::schema::user::dsl::*;
-    diesel::update(user.find(ses_u.id))
-      .set(layout.eq(body))
-      .execute(&mut pool.get().unwrap())
-      .unwrap()
+    diesel::update(user.find(ses_u.id)).set(layout.eq(body)).execute(conn).unwrap();
+    Ok::<_, Infallible>(())
   })
   .await?;
+  hub.layout(ses_u.id);
   Ok(HttpResponse::NoContent().finish())
 }
 
@@ -43,9 +48,10 @@ pub async fn own_boards(
   pool: web::Data<DbPool>,
   ses_u: AuthdUser,
 ) -> actix_web::Result<impl Responder> {
-  let boards: Vec<i64> = web::block(move || {
+  let boards: Vec<i64> = db::read(&pool, move |conn| {
     use crate::schema::board::dsl::*;
-    board.filter(owner_id.eq(ses_u.id)).select(id).load(&mut pool.get().unwrap()).unwrap()
+    let own = board.filter(owner_id.eq(ses_u.id).and(deleted_at.is_null()));
+    Ok::<_, Infallible>(own.select(id).load(conn).unwrap())
   })
   .await?;
   Ok(HttpResponse::Ok().json(boards))
@@ -0,0 +1,115 @@
+//! A small load generator for the server's hot paths. It talks plain HTTP/1.1
+//! over TCP so that the numbers reflect the server and not a client library.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use common::{FreshBoard, NewBoardForm, TokenPair, UserDataForm, Visibility};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+  /// Address of a running server
+  #[arg(long, default_value = "127.0.0.1:8081")]
+  pub addr: String,
+  /// Number of clients sending requests in parallel
+  #[arg(long, default_value_t = 16)]
+  pub clients: usize,
+  /// Number of requests each client sends per scenario
+  #[arg(long, default_value_t = 20)]
+  pub requests: usize,
+}
+
+struct Reply {
+  status: u16,
+  body: String,
+}
+
+fn request(addr: &str, head: &str, body: Option<&str>) -> Reply {
+  let mut stream = TcpStream::connect(addr).expect("Server should be running");
+  let body = body.unwrap_or("");
+  let msg = format!(
+    "{head}\r\nHost: {addr}\r\nConnection: close\r\nContent-Type: application/json\r\n\
+     Content-Length: {}\r\n\r\n{body}",
+    body.len()
+  );
+  stream.write_all(msg.as_bytes()).unwrap();
+  let mut rep = String::new();
+  stream.read_to_string(&mut rep).unwrap();
+  let (head, body) = rep.split_once("\r\n\r\n").expect("Malformed HTTP response");
+  let status = head.split(' ').nth(1).and_then(|s| s.parse().ok()).expect("Malformed status");
+  Reply { status, body: body.to_string() }
+}
+
+fn json<T: DeserializeOwned>(rep: Reply, what: &str) -> T {
+  assert!((200..300).contains(&rep.status), "{what} failed with {}: {}", rep.status, rep.body);
+  serde_json::from_str(&rep.body).unwrap()
+}
+
+fn body(t: &impl Serialize) -> String { serde_json::to_string(t).unwrap() }
+
+/// Send `requests` requests from each of `clients` threads at once and print
+/// the latency distribution and the number of failed requests
+fn scenario(name: &str, args: &BenchArgs, send: impl Fn() -> Reply + Sync) {
+  let barrier = Barrier::new(args.clients);
+  let start = Instant::now();
+  let results = thread::scope(|s| {
+    let threads = (0..args.clients)
+      .map(|_| {
+        s.spawn(|| {
+          barrier.wait();
+          (0..args.requests)
+            .map(|_| {
+              let t = Instant::now();
+              let rep = send();
+              (t.elapsed(), rep.status < 400)
+            })
+            .collect::<Vec<_>>()
+        })
+      })
+      .collect::<Vec<_>>();
+    threads.into_iter().flat_map(|t| t.join().unwrap()).collect::<Vec<_>>()
+  });
+  let total = start.elapsed();
+  let failed = results.iter().filter(|(_, ok)| !ok).count();
+  let mut samples = results.into_iter().map(|(t, _)| t).collect::<Vec<_>>();
+  samples.sort();
+  let pct = |p: usize| samples[(samples.len() - 1) * p / 100];
+  let mean = samples.iter().sum::<Duration>() / samples.len() as u32;
+  println!(
+    "{name:>12}: {} req in {total:.2?} ({:.1} req/s), {failed} failed, mean {mean:.2?}, \
+     p50 {:.2?}, p90 {:.2?}, p99 {:.2?}, max {:.2?}",
+    samples.len(),
+    samples.len() as f64 / total.as_secs_f64(),
+    pct(50),
+    pct(90),
+    pct(99),
+    pct(100),
+  );
+}
+
+pub fn run(args: BenchArgs) {
+  let addr = &args.addr[..];
+  let stamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+  let creds = body(&UserDataForm { name: format!("bench-{stamp}"), pass: "bench".to_string() });
+  let tokens: TokenPair =
+    json(request(addr, "POST /auth/register HTTP/1.1", Some(&creds)), "Registration");
+  let form = NewBoardForm {
+    name: "Bench".to_string(),
+    public_mut: false,
+    layout: r#"{"V1":{"sections":[]}}"#.to_string(),
+    visibility: Visibility::Unlisted,
+  };
+  let head = format!("POST /new_board HTTP/1.1\r\nAuthorization: Bearer {}", tokens.access_token);
+  let board: FreshBoard = json(request(addr, &head, Some(&body(&form))), "Board creation");
+  println!("{} clients, {} requests each", args.clients, args.requests);
+  scenario("login", &args, || request(addr, "POST /auth/login HTTP/1.1", Some(&creds)));
+  let details = format!("GET /boards/{} HTTP/1.1", board.url);
+  scenario("board", &args, || request(addr, &details, None));
+  let layout = format!("GET /boards/{}/layout HTTP/1.1", board.url);
+  scenario("board layout", &args, || request(addr, &layout, None));
+}
@@ -1,9 +1,12 @@
+mod bench;
+
 use std::path::PathBuf;
 use std::process::{Command, ExitCode};
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+use bench::BenchArgs;
 use clap::Parser;
 use common::clone;
 
@@ -16,6 +19,9 @@ struct Args {
 #[derive(clap::Subcommand, Debug)]
 enum Cmd {
   Run,
+  /// Measure login and board fetch latency under concurrent load against a
+  /// running server
+  Bench(BenchArgs),
   Diesel {
     #[arg(trailing_var_arg = true, allow_hyphen_values = true, hide = true)]
     args: Vec<String>,
@@ -57,6 +63,10 @@ fn main() -> ExitCode {
       });
       ExitCode::SUCCESS
     },
+    Cmd::Bench(args) => {
+      bench::run(args);
+      ExitCode::SUCCESS
+    },
     Cmd::Diesel { args } => ExitCode::from(
       Command::new("diesel")
         .args(args)